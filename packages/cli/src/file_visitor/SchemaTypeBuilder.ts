@@ -161,7 +161,7 @@ export default class SchemaTypeBuilder {
       return `{
   ${Object.entries(recordTypes)
     .map(([key, value]) => {
-      return `readonly ${key.replace("main.", "")}: Readonly<{
+      return `readonly ${relationKey(key)}: Readonly<{
     ${Object.entries(value)
       .map(([key, value]) => {
         return `${key}: ${value}`;
@@ -178,3 +178,8 @@ export default class SchemaTypeBuilder {
     }
   }
 }
+
+// `main.foo` -> `foo`. Relations in other databases keep their qualifier. E.g., `"temp.foo"`
+function relationKey(name: string): string {
+  return name.startsWith("main.") ? name.substring(5) : JSON.stringify(name);
+}
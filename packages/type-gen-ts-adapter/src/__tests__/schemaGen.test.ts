@@ -34,6 +34,19 @@ test("generates types for table schemas", () => {
         },
       },
     ],
+    [
+      "check constraint enums",
+      `CREATE TABLE foo (
+        status TEXT NOT NULL CHECK (status IN ('open', 'closed', 'archived')),
+        priority INT CHECK (priority BETWEEN 1 AND 3)
+      );`,
+      {
+        "main.foo": {
+          status: "'open' | 'closed' | 'archived'",
+          priority: "1 | 2 | 3 | null",
+        },
+      },
+    ],
   ] as const;
 
   runTests(cases);
@@ -1,6 +1,10 @@
 import {
   get_relation_shapes,
+  analyze_schema,
   get_query_result_shapes,
+  analyze_query,
+  get_query_dependencies,
+  check_query,
 } from "typed-sql-type-gen";
 
 type RelationName = string;
@@ -28,15 +32,165 @@ type BuiltinType =
 
 type ColType = [TypeKind, BuiltinType | null, CustomType][];
 type Col = [ColName, ColType];
-export type NamedRelation = [RelationName, Col[]];
+type RelationInfo = {
+  strict: boolean;
+  withoutRowid: boolean;
+  // the module backing a virtual table. E.g., "fts5"
+  module: string | null;
+  // selectable by name but left out of `*`
+  hiddenColumns: ColName[];
+  // primary key, UNIQUE constraints and unique indexes
+  uniqueKeys: ColName[][];
+  // the CREATE VIEW statement of a view
+  view: string | null;
+  // CREATE TRIGGER statements of the relation's triggers
+  triggers: string[];
+  // the type name each column was declared with, in column order
+  declaredTypes: (string | null)[];
+};
+// the info is optional when passing a schema in. `[name, cols]` pairs from before it existed still work.
+export type NamedRelation = [RelationName, Col[], RelationInfo?];
 type Relation = [RelationName | null, Col[]];
+type Affinity = "Integer" | "Text" | "Blob" | "Real" | "Numeric";
+// how a runtime should decode the column at `position`
+export type Converter = {
+  position: number;
+  typeName: string;
+  // the affinity of the column's declared type, which decides how SQLite stores its values
+  affinity: Affinity;
+};
+// a bound parameter. `index` is what to pass to sqlite3_bind_*
+export type Param = {
+  index: number;
+  name: string | null;
+  colType: ColType;
+};
+// how many rows a statement returns. `Zero` for writes without RETURNING.
+// Lets callers type results as `T`, `T | undefined` or `T[]`.
+export type Cardinality = "Zero" | "One" | "ZeroOrOne" | "Many";
+export type ResultShape = {
+  relation: Relation;
+  converters: Converter[];
+  params: Param[];
+  cardinality: Cardinality;
+  // includes errors that left columns "Unresolved" rather than failing the whole statement
+  diagnostics: Diagnostic[];
+  // one per column of `relation`. null for computed columns.
+  origins: (ColumnOrigin | null)[];
+  statement: StatementInfo;
+  // where the statement is in the query
+  span: Span;
+};
+// `offset` is in bytes. `line` and `column` start at 1 and columns count characters.
+export type Position = {
+  offset: number;
+  line: number;
+  column: number;
+};
+// `end` is exclusive
+export type Span = {
+  start: Position;
+  end: Position;
+};
+export type StatementKind =
+  | "Select"
+  | "Insert"
+  | "Update"
+  | "Delete"
+  | "Ddl"
+  | "Pragma"
+  | "Transaction"
+  | "Attach"
+  | "Explain"
+  | "Other";
+// like sqlite3_stmt_readonly. Tells a driver whether to `run` a statement or fetch its rows.
+export type StatementInfo = {
+  kind: StatementKind;
+  readOnly: boolean;
+  returnsRows: boolean;
+  // e.g., VACUUM. No other connection can use the database while it runs.
+  needsExclusive: boolean;
+};
+// the table column a result column is read from, like sqlite3_column_table_name / sqlite3_column_origin_name
+export type ColumnOrigin = {
+  db: string;
+  table: string;
+  column: ColName;
+};
+
+export type Severity = "Error" | "Warning" | "Hint";
+// stable across versions so tools can match on them
+export type DiagnosticCode =
+  | "syntax"
+  | "no-such-table"
+  | "no-such-column"
+  | "ambiguous-column"
+  | "unaliased-expression"
+  | "duplicate-column"
+  | "out-of-range"
+  | "join-column"
+  | "column-count"
+  | "unknown-type"
+  | "unknown-module"
+  | "unresolved"
+  | "other";
+export type Diagnostic = {
+  code: DiagnosticCode;
+  severity: Severity;
+  message: string;
+  // where in the analyzed SQL the problem is
+  span: Span | null;
+  // other places that explain the problem, e.g. each table an ambiguous column could be from
+  related: { span: Span; message: string }[];
+  // alternative changes that would fix the problem. Each replaces the text of its spans.
+  fixes: { message: string; edits: { span: Span; text: string }[] }[];
+};
+export type SchemaShape = {
+  relations: NamedRelation[];
+  diagnostics: Diagnostic[];
+};
 
 type ParsedRelation = {
   [key: string]: string;
 };
 
-export function getDdlRelations(query: string): NamedRelation[] {
-  return get_relation_shapes(query) as NamedRelation[];
+export type Options = {
+  // type columns by their SQLite storage affinity rather than their declared type name
+  affinityTypes?: boolean;
+  // declared type pattern -> type. E.g., [["TIMESTAMP", "Int"], ["VARCHAR(*)", "String"]]
+  // checked in order before the built-in rules
+  typeMappings?: [string, BuiltinType][];
+  // shapes for virtual table modules other than fts5, rtree and rtree_i32
+  modules?: ModuleShape[];
+  // rows are read as tuples by position, like `stmt.raw()`. Unaliased expressions and duplicate names are allowed.
+  arrayMode?: boolean;
+  // unaliased expressions are named after their text as written, like SQLite does. E.g., `count(*)`.
+  sqliteColumnNames?: boolean;
+  // how to report result columns that share a name, like `SELECT a.id, b.id`. Defaults to "Warning".
+  duplicateColumns?: Severity;
+  // list the columns each statement reads and writes in `getQueryDependencies`
+  columnDependencies?: boolean;
+  // result columns we can't type are "Unresolved" with the reason as their third element.
+  // "Error" and "Warning" report them, "Any" types them as Any. Defaults to "Warning".
+  unresolvedTypes?: "Error" | "Warning" | "Any";
+};
+
+export type ModuleShape = {
+  name: string;
+  columns: Col[];
+  hiddenColumns?: Col[];
+};
+
+export function getDdlRelations(
+  query: string,
+  options?: Options
+): NamedRelation[] {
+  return get_relation_shapes(query, options) as NamedRelation[];
+}
+
+// like getDdlRelations but also types views and type-checks trigger bodies
+export function analyzeSchema(query: string, options?: Options): SchemaShape {
+  return analyze_schema(query, options) as SchemaShape;
 }
 
 export function parseDdlRelations(rawRelations: NamedRelation[]): {
@@ -51,17 +205,66 @@ export function parseDdlRelations(rawRelations: NamedRelation[]): {
 
 export function getQueryRelations(
   query: string,
-  schema: NamedRelation[]
+  schema: NamedRelation[],
+  options?: Options
 ): Relation[] {
-  const rawShapes = get_query_result_shapes(query, schema) as Relation[];
+  const rawShapes = get_query_result_shapes(
+    query,
+    schema,
+    options
+  ) as Relation[];
   // TODO: change to structured type
   return rawShapes;
 }
 
+export function analyzeQuery(
+  query: string,
+  schema: NamedRelation[],
+  options?: Options
+): ResultShape[] {
+  return analyze_query(query, schema, options) as ResultShape[];
+}
+
+// what each statement reads and writes, including through views and the triggers its writes fire.
+// Relations are `db.name`. Columns are only listed with `columnDependencies`.
+export type Dependencies = {
+  reads: RelationName[];
+  writes: RelationName[];
+  readColumns: [RelationName, ColName][];
+  writeColumns: [RelationName, ColName][];
+  // views and triggers whose recorded SQL couldn't be followed. What they read and write is missing above.
+  diagnostics: Diagnostic[];
+};
+
+export function getQueryDependencies(
+  query: string,
+  schema: NamedRelation[],
+  options?: Options
+): Dependencies[] {
+  return get_query_dependencies(query, schema, options) as Dependencies[];
+}
+
+// every problem in the query, each pointing at where it is.
+// A syntax error is reported rather than thrown, unlike analyzeQuery.
+export function checkQuery(
+  query: string,
+  schema: NamedRelation[],
+  options?: Options
+): Diagnostic[] {
+  return check_query(query, schema, options) as Diagnostic[];
+}
+
 export function parseQueryRelations(relations: Relation[]): ParsedRelation[] {
   return relations.map((r) => parseRelation(r));
 }
 
+// for shapes analyzed with `arrayMode`. One TS type per column, in order.
+export function parseQueryTuples(relations: Relation[]): string[][] {
+  return relations.map((r) =>
+    r[1].map((col) => colTypeToTsTypeString(col[1], false))
+  );
+}
+
 function parseRelation(
   relation: Relation,
   ddl: boolean = false
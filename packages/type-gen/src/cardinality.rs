@@ -0,0 +1,203 @@
+use sqlite3_parser::ast::{
+    Cmd, Expr, FromClause, FunctionTail, Id, Limit, Literal, Name, OneSelect, Operator,
+    ResultColumn, Select, SelectTable, Stmt,
+};
+
+use crate::queries::{self, Context, FromRelation};
+use crate::types::*;
+use crate::util;
+use crate::walk::walk_expr;
+
+// How many rows a statement returns, erring towards `Many` when we can't tell.
+// - an aggregate without GROUP BY returns exactly one row
+// - `LIMIT 1` or equality on every column of a unique key returns zero or one
+// - INSERT, UPDATE and DELETE without RETURNING return none
+pub fn cardinality(cmd: &Cmd, ctx: &Context) -> Cardinality {
+    match cmd {
+        Cmd::Explain(_) | Cmd::ExplainQueryPlan(_) => Cardinality::Many,
+        Cmd::Stmt(Stmt::Select(select)) => select_cardinality(select, ctx),
+        Cmd::Stmt(
+            Stmt::Insert { returning, .. }
+            | Stmt::Update { returning, .. }
+            | Stmt::Delete { returning, .. },
+        ) => match returning {
+            // TODO: RETURNING from an INSERT of a single row of VALUES returns one row
+            Some(_) => Cardinality::Many,
+            None => Cardinality::Zero,
+        },
+        Cmd::Stmt(_) => Cardinality::Many,
+    }
+}
+
+fn select_cardinality(select: &Select, ctx: &Context) -> Cardinality {
+    let ret = match &select.body.select {
+        _ if select.body.compounds.is_some() => Cardinality::Many,
+        OneSelect::Values(rows) if rows.len() == 1 => Cardinality::One,
+        OneSelect::Values(_) => Cardinality::Many,
+        OneSelect::Select {
+            columns,
+            from,
+            where_clause,
+            group_by,
+            ..
+        } => match from {
+            // an aggregate over no rows still returns a row
+            _ if group_by.is_none() && columns.iter().any(is_aggregate_column) => Cardinality::One,
+            None if where_clause.is_none() => Cardinality::One,
+            None => Cardinality::ZeroOrOne,
+            Some(from) => match where_clause {
+                Some(where_clause) if is_unique_lookup(select, from, where_clause, ctx) => {
+                    Cardinality::ZeroOrOne
+                }
+                _ => Cardinality::Many,
+            },
+        },
+    };
+    match (&select.limit, ret) {
+        (Some(Limit { expr, offset }), _) if is_at_most_one(expr) => match (offset, ret) {
+            (None, Cardinality::One) => Cardinality::One,
+            _ => Cardinality::ZeroOrOne,
+        },
+        _ => ret,
+    }
+}
+
+fn is_at_most_one(e: &Expr) -> bool {
+    matches!(e, Expr::Literal(Literal::Numeric(n)) if n.parse::<u64>().is_ok_and(|n| n <= 1))
+}
+
+// A result column that calls an aggregate function outside of a window or subquery
+fn is_aggregate_column(column: &ResultColumn) -> bool {
+    let ResultColumn::Expr(e, _) = column else {
+        return false;
+    };
+    let mut ret = false;
+    walk_expr(e, &mut |e| match e {
+        Expr::FunctionCall {
+            name: Id(name),
+            args,
+            filter_over,
+            ..
+        } => {
+            let argc = args.as_ref().map_or(0, |args| args.len());
+            ret |= !is_window(filter_over) && is_aggregate_fn(name, argc);
+        }
+        Expr::FunctionCallStar {
+            name: Id(name),
+            filter_over,
+        } => ret |= !is_window(filter_over) && is_aggregate_fn(name, 0),
+        _ => {}
+    });
+    ret
+}
+
+fn is_window(filter_over: &Option<FunctionTail>) -> bool {
+    filter_over
+        .as_ref()
+        .is_some_and(|tail| tail.over_clause.is_some())
+}
+
+// https://www.sqlite.org/lang_aggfunc.html
+// min and max are only aggregates when given a single argument. With more they pick between their arguments.
+fn is_aggregate_fn(name: &str, argc: usize) -> bool {
+    [
+        "avg",
+        "count",
+        "group_concat",
+        "string_agg",
+        "sum",
+        "total",
+        "json_group_array",
+        "json_group_object",
+        "jsonb_group_array",
+        "jsonb_group_object",
+    ]
+    .iter()
+    .any(|f| util::ident_eq(f, name))
+        || (argc == 1 && (util::ident_eq(name, "min") || util::ident_eq(name, "max")))
+}
+
+// Whether the WHERE clause pins down every column of a unique key of the one table selected from.
+fn is_unique_lookup(
+    select: &Select,
+    from: &FromClause,
+    where_clause: &Expr,
+    ctx: &Context,
+) -> bool {
+    let (Some(table), None) = (&from.select, &from.joins) else {
+        return false;
+    };
+    let SelectTable::Table(qualified_name, maybe_as, _) = table.as_ref() else {
+        return false;
+    };
+    let Ok(ctes) = queries::with_relations(select, &[], ctx) else {
+        return false;
+    };
+    let ctx = Context {
+        ctes: &ctes,
+        ..*ctx
+    };
+    let Ok(relation) = queries::maybe_aliased_table_to_relation(qualified_name, maybe_as, &ctx)
+    else {
+        return false;
+    };
+
+    let mut pinned = vec![];
+    pinned_cols(where_clause, &relation, &mut pinned);
+    let rowid = (!relation.info.without_rowid).then(|| vec!["rowid".to_string()]);
+    relation
+        .info
+        .unique_keys
+        .iter()
+        .chain(rowid.iter())
+        .any(|key| {
+            key.iter()
+                .all(|col| pinned.iter().any(|p| util::ident_eq(p, col)))
+        })
+}
+
+// Columns of `relation` that an AND term of `e` compares for equality with something that isn't another of its columns.
+// Equality rejects NULL so a UNIQUE column that allows many NULLs still matches at most one row.
+fn pinned_cols(e: &Expr, relation: &FromRelation, pinned: &mut Vec<String>) {
+    match e {
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => pinned_cols(&exprs[0], relation, pinned),
+        Expr::Binary(lhs, Operator::And, rhs) => {
+            pinned_cols(lhs, relation, pinned);
+            pinned_cols(rhs, relation, pinned);
+        }
+        Expr::Binary(lhs, Operator::Equals, rhs) => {
+            for (col, other) in [(lhs, rhs), (rhs, lhs)] {
+                if let Some(col) = col_of(col, relation) {
+                    if !refers_to(other, relation) {
+                        pinned.push(col);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// The column of `relation` that `e` refers to, with rowid aliases as `rowid`
+fn col_of(e: &Expr, relation: &FromRelation) -> Option<String> {
+    let name = match e {
+        Expr::Id(Id(name)) | Expr::Name(Name(name)) => util::unquote_ident(name),
+        Expr::Qualified(Name(table), Name(name))
+            if relation.is_named(None, &util::unquote_ident(table)) =>
+        {
+            util::unquote_ident(name)
+        }
+        _ => return None,
+    };
+    match relation.col(&name) {
+        Some(col) => Some(col.0.to_string()),
+        None if queries::is_rowid_alias(&name) => Some("rowid".to_string()),
+        None => None,
+    }
+}
+
+fn refers_to(e: &Expr, relation: &FromRelation) -> bool {
+    let mut ret = false;
+    walk_expr(e, &mut |e| ret |= col_of(e, relation).is_some());
+    ret
+}
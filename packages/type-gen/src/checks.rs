@@ -0,0 +1,209 @@
+use sqlite3_parser::ast::{Expr, Id, Literal, Name, Operator, UnaryOperator};
+
+use crate::types::*;
+use crate::util;
+
+// BETWEEN over a wider range than this is left as a plain number rather than a union of literals.
+const MAX_BETWEEN_LITERALS: i64 = 32;
+
+// Given a CHECK constraint expression, return the set of literals it restricts `col_name` to.
+// e.g., `CHECK (status IN ('open', 'closed'))` -> `'open' | 'closed'`
+//
+// We only understand the shapes people actually write for enums:
+// - `col IN (...)`
+// - `col = a OR col = b OR ...`
+// - `col BETWEEN 1 AND 5` for small integer ranges
+// - any of the above joined to unrelated conditions via AND
+// Anything else returns `None` and the declared type is left alone.
+pub fn literal_domain(col_name: &str, expr: &Expr) -> Option<ColType> {
+    match expr {
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => literal_domain(col_name, &exprs[0]),
+        Expr::InList {
+            lhs,
+            not: false,
+            rhs: Some(list),
+        } if refers_to(col_name, lhs) => {
+            let mut ret = vec![];
+            for e in list {
+                union(&mut ret, literal(e)?);
+            }
+            Some(ret)
+        }
+        Expr::Binary(lhs, Operator::Equals, rhs) => {
+            if refers_to(col_name, lhs) {
+                literal(rhs)
+            } else if refers_to(col_name, rhs) {
+                literal(lhs)
+            } else {
+                None
+            }
+        }
+        Expr::Binary(lhs, Operator::Or, rhs) => {
+            // every branch of the OR must restrict the column or the column is unrestricted
+            let mut ret = literal_domain(col_name, lhs)?;
+            union(&mut ret, literal_domain(col_name, rhs)?);
+            Some(ret)
+        }
+        Expr::Binary(lhs, Operator::And, rhs) => {
+            match (literal_domain(col_name, lhs), literal_domain(col_name, rhs)) {
+                (Some(l), Some(r)) => Some(l.into_iter().filter(|t| r.contains(t)).collect()),
+                (Some(l), None) => Some(l),
+                (None, Some(r)) => Some(r),
+                (None, None) => None,
+            }
+        }
+        Expr::Between {
+            lhs,
+            not: false,
+            start,
+            end,
+        } if refers_to(col_name, lhs) => {
+            let start = integer(start)?;
+            let end = integer(end)?;
+            if end < start || end - start >= MAX_BETWEEN_LITERALS {
+                return None;
+            }
+            Some(
+                (start..=end)
+                    .map(|i| {
+                        (
+                            TypeKind::Literal,
+                            Some(BuiltinType::Number),
+                            Some(i.to_string()),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn refers_to(col_name: &str, expr: &Expr) -> bool {
+    match expr {
+        Expr::Id(Id(name)) | Expr::Name(Name(name)) | Expr::Qualified(_, Name(name)) => {
+            util::ident_eq(&util::unquote_ident(name), col_name)
+        }
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => refers_to(col_name, &exprs[0]),
+        _ => false,
+    }
+}
+
+// Only numbers and strings can be part of a domain.
+// A NULL in an IN list makes the CHECK pass for any value so it can't narrow anything.
+fn literal(expr: &Expr) -> Option<ColType> {
+    match expr {
+        Expr::Literal(lit @ (Literal::Numeric(_) | Literal::String(_))) => {
+            Some(literal_to_type(lit))
+        }
+        Expr::Unary(UnaryOperator::Negative, e) => match e.as_ref() {
+            Expr::Literal(Literal::Numeric(n)) => Some(vec![(
+                TypeKind::Literal,
+                Some(BuiltinType::Number),
+                Some(format!("-{}", n)),
+            )]),
+            _ => None,
+        },
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => literal(&exprs[0]),
+        _ => None,
+    }
+}
+
+fn integer(expr: &Expr) -> Option<i64> {
+    match literal(expr)?.first() {
+        Some((_, Some(BuiltinType::Number), Some(n))) => n.parse().ok(),
+        _ => None,
+    }
+}
+
+fn union(into: &mut ColType, other: ColType) {
+    for t in other {
+        if !into.contains(&t) {
+            into.push(t);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fallible_iterator::FallibleIterator;
+    use sqlite3_parser::{
+        ast::{Cmd, OneSelect, ResultColumn, Stmt},
+        lexer::sql::Parser,
+    };
+
+    fn domain(check: &str) -> Option<ColType> {
+        let sql = format!("SELECT {}", check);
+        let mut parser = Parser::new(sql.as_bytes());
+        match parser.next().unwrap() {
+            Some(Cmd::Stmt(Stmt::Select(select))) => match select.body.select {
+                OneSelect::Select { columns, .. } => match &columns[0] {
+                    ResultColumn::Expr(e, _) => literal_domain("status", e),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn literals(values: &[&str]) -> ColType {
+        values
+            .iter()
+            .map(|v| {
+                (
+                    TypeKind::Literal,
+                    Some(if v.starts_with('\'') {
+                        BuiltinType::String
+                    } else {
+                        BuiltinType::Number
+                    }),
+                    Some(v.to_string()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn in_list() {
+        assert_eq!(
+            domain("status IN ('open', 'closed', 'archived')"),
+            Some(literals(&["'open'", "'closed'", "'archived'"]))
+        );
+        assert_eq!(domain("status NOT IN ('open')"), None);
+        assert_eq!(domain("status IN ('open', NULL)"), None);
+        assert_eq!(domain("other IN ('open')"), None);
+    }
+
+    #[test]
+    fn or_chain() {
+        assert_eq!(
+            domain("status = 1 OR 2 = status OR (status = -1)"),
+            Some(literals(&["1", "2", "-1"]))
+        );
+        assert_eq!(domain("status = 1 OR other = 2"), None);
+    }
+
+    #[test]
+    fn between() {
+        assert_eq!(
+            domain("status BETWEEN 1 AND 3"),
+            Some(literals(&["1", "2", "3"]))
+        );
+        assert_eq!(domain("status BETWEEN 1 AND 1000"), None);
+        assert_eq!(domain("status BETWEEN 1.5 AND 3"), None);
+    }
+
+    #[test]
+    fn and() {
+        assert_eq!(
+            domain("length(other) > 0 AND status IN ('a', 'b')"),
+            Some(literals(&["'a'", "'b'"]))
+        );
+        assert_eq!(
+            domain("status IN ('a', 'b') AND status IN ('b', 'c')"),
+            Some(literals(&["'b'"]))
+        );
+    }
+}
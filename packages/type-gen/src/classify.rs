@@ -0,0 +1,119 @@
+use sqlite3_parser::ast::{Cmd, PragmaBody, QualifiedName, Stmt, TransactionType};
+
+use crate::types::*;
+use crate::util;
+
+// What running a statement involves. `read_only` follows `sqlite3_stmt_readonly`:
+// transaction control, ATTACH and DETACH don't write to the database so they count as read-only.
+// https://www.sqlite.org/c3ref/stmt_readonly.html
+pub fn classify(cmd: &Cmd) -> StatementInfo {
+    let stmt = match cmd {
+        Cmd::Explain(_) | Cmd::ExplainQueryPlan(_) => {
+            return StatementInfo {
+                kind: StatementKind::Explain,
+                read_only: true,
+                returns_rows: true,
+                needs_exclusive: false,
+            }
+        }
+        Cmd::Stmt(stmt) => stmt,
+    };
+    let (kind, read_only, returns_rows) = match stmt {
+        Stmt::Select(_) => (StatementKind::Select, true, true),
+        Stmt::Insert { returning, .. } => (StatementKind::Insert, false, returning.is_some()),
+        Stmt::Update { returning, .. } => (StatementKind::Update, false, returning.is_some()),
+        Stmt::Delete { returning, .. } => (StatementKind::Delete, false, returning.is_some()),
+        Stmt::AlterTable(..)
+        | Stmt::CreateIndex { .. }
+        | Stmt::CreateTable { .. }
+        | Stmt::CreateTrigger { .. }
+        | Stmt::CreateView { .. }
+        | Stmt::CreateVirtualTable { .. }
+        | Stmt::DropIndex { .. }
+        | Stmt::DropTable { .. }
+        | Stmt::DropTrigger { .. }
+        | Stmt::DropView { .. } => (StatementKind::Ddl, false, false),
+        Stmt::Pragma(name, body) => (
+            StatementKind::Pragma,
+            !pragma_writes(name, body),
+            pragma_returns_rows(name, body),
+        ),
+        Stmt::Begin(..)
+        | Stmt::Commit(_)
+        | Stmt::Rollback { .. }
+        | Stmt::Savepoint(_)
+        | Stmt::Release(_) => (StatementKind::Transaction, true, false),
+        Stmt::Attach { .. } | Stmt::Detach(_) => (StatementKind::Attach, true, false),
+        Stmt::Analyze(_) | Stmt::Reindex { .. } | Stmt::Vacuum(..) => {
+            (StatementKind::Other, false, false)
+        }
+    };
+    StatementInfo {
+        kind,
+        read_only,
+        returns_rows,
+        needs_exclusive: needs_exclusive(stmt),
+    }
+}
+
+// Statements that need the database to themselves.
+// - VACUUM rebuilds the whole file and can't run within a transaction
+// - changing the journal mode, page size or auto-vacuum mode rewrites how the file is laid out
+// - BEGIN EXCLUSIVE keeps every other connection out
+fn needs_exclusive(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Vacuum(..) => true,
+        Stmt::Begin(Some(TransactionType::Exclusive), _) => true,
+        Stmt::Pragma(name, body) if sets_value(name, body) => {
+            is_pragma(name, &["journal_mode", "page_size", "auto_vacuum"])
+        }
+        _ => false,
+    }
+}
+
+// `PRAGMA x = y` changes a setting. A few pragmas change things even when only called.
+fn pragma_writes(name: &QualifiedName, body: &Option<PragmaBody>) -> bool {
+    sets_value(name, body) || is_pragma(name, &["incremental_vacuum", "optimize", "wal_checkpoint"])
+}
+
+// Pragmas report their value when queried. Most settings return nothing when set,
+// except those that report the resulting mode.
+fn pragma_returns_rows(name: &QualifiedName, body: &Option<PragmaBody>) -> bool {
+    if sets_value(name, body) {
+        is_pragma(name, &["journal_mode", "locking_mode"])
+    } else {
+        true
+    }
+}
+
+// `PRAGMA x(y)` is another way to write `PRAGMA x = y`, except for pragmas whose argument is
+// what they look at or act on rather than a new value.
+fn sets_value(name: &QualifiedName, body: &Option<PragmaBody>) -> bool {
+    match body {
+        Some(PragmaBody::Equals(_)) => true,
+        Some(PragmaBody::Call(_)) => !is_pragma(
+            name,
+            &[
+                "foreign_key_check",
+                "foreign_key_list",
+                "incremental_vacuum",
+                "index_info",
+                "index_list",
+                "index_xinfo",
+                "integrity_check",
+                "optimize",
+                "quick_check",
+                "table_info",
+                "table_list",
+                "table_xinfo",
+                "wal_checkpoint",
+            ],
+        ),
+        None => false,
+    }
+}
+
+fn is_pragma(name: &QualifiedName, names: &[&str]) -> bool {
+    let name = util::unquote_ident(&name.name.0);
+    names.iter().any(|n| util::ident_eq(n, &name))
+}
@@ -1,74 +1,1070 @@
 use crate::error::Error;
 use fallible_iterator::FallibleIterator;
 use sqlite3_parser::{
-    ast::{Cmd, ColumnConstraint, ColumnDefinition, CreateTableBody, Stmt},
+    ast::{
+        Cmd, ColumnConstraint, ColumnDefinition, CreateTableBody, Expr, Id, IndexedColumn, Literal,
+        Name, QualifiedName, Select, SortedColumn, Stmt, TableConstraint, TableOptions, Type,
+        TypeSize,
+    },
     lexer::sql::Parser,
 };
 
+use crate::checks;
+use crate::diagnostics::{Code, Diagnostic, Severity};
+use crate::options::Options;
+use crate::queries::{self, Context};
+use crate::spans;
+use crate::triggers;
 use crate::types::*;
 use crate::util;
+use std::borrow::Cow;
 
-pub fn get_relation_shapes(ddl: String) -> Result<Vec<NamedRelation>, Error> {
+pub fn get_relation_shapes(ddl: String, options: &Options) -> Result<Vec<NamedRelation>, Error> {
+    let mut relations = analyze_schema(ddl, options)?.relations;
+    relations.retain(|(_, _, info)| info.view.is_none());
+    Ok(relations)
+}
+
+// Like `get_relation_shapes` but also checks the parts of the schema SQLite only checks at runtime.
+// Its relations include the views, which queries and dependencies can read through.
+pub fn analyze_schema(ddl: String, options: &Options) -> Result<SchemaShape, Error> {
     let mut parser = Parser::new(ddl.as_bytes());
-    let mut ret = vec![];
+    let mut relations: Vec<NamedRelation> = vec![];
+    let mut triggers = vec![];
+    let mut databases = vec!["main".to_string(), "temp".to_string()];
+    let mut diagnostics = vec![];
+    let mut stmt_start = 0;
 
     while let Some(cmd) = parser.next()? {
-        match cmd {
-            Cmd::Stmt(stmt) => {
-                if let Some(record) = maybe_record(stmt)? {
-                    ret.push(record)
+        let stmt_end = spans::offset_of(&ddl, parser.line(), parser.column());
+        // Views and triggers keep their SQL so what they read and write can be followed later
+        let sql = ddl[stmt_start..stmt_end].trim().to_string();
+        let stmt_range = (stmt_start, stmt_end);
+        stmt_start = stmt_end;
+        if let Cmd::Stmt(stmt) = cmd {
+            match stmt {
+                Stmt::CreateTrigger { .. } => triggers.push((stmt, sql, stmt_range)),
+                Stmt::CreateView {
+                    temporary,
+                    view_name,
+                    columns,
+                    select,
+                    ..
+                } => {
+                    let name = relation_name(&view_name, temporary, &databases)?;
+                    match view_columns(&select, &columns, &sql, &relations, options) {
+                        Ok(cols) => relations.push((
+                            name,
+                            cols,
+                            RelationInfo {
+                                view: Some(sql),
+                                ..RelationInfo::default()
+                            },
+                        )),
+                        // SQLite only resolves a view when it is used so one we can't type shouldn't sink the schema
+                        Err(e) => diagnostics.push(
+                            Diagnostic::from(e)
+                                .within(&format!("in view {}", name))
+                                .locate(&ddl, stmt_range.0, stmt_range.1),
+                        ),
+                    }
+                }
+                Stmt::CreateIndex {
+                    unique: true,
+                    idx_name,
+                    tbl_name,
+                    columns,
+                    where_clause: None,
+                    ..
+                } => {
+                    // a partial index only keeps the rows it covers unique
+                    if let Some(key) = key_col_names(&columns) {
+                        let db = idx_name
+                            .db_name
+                            .map(|n| util::unquote_ident(&n.0).to_string());
+                        let tbl_name = util::unquote_ident(&tbl_name.0);
+                        if let Some(relation) = indexed_relation(&mut relations, db, &tbl_name) {
+                            relation.2.unique_keys.push(key);
+                        }
+                    }
+                }
+                Stmt::Attach { db_name, .. } => {
+                    let db_name = database_name(&db_name)?;
+                    if find_database(&databases, &db_name).is_some() {
+                        return Err(Error::Other(format!(
+                            "database {} is already in use",
+                            db_name
+                        )));
+                    }
+                    databases.push(db_name);
+                }
+                Stmt::Detach(db_name) => {
+                    let db_name = database_name(&db_name)?;
+                    match find_database(&databases, &db_name) {
+                        Some(i) if i > 1 => {
+                            let db_name = databases.remove(i);
+                            relations.retain(|r| split_relation_name(&r.0).0 != db_name);
+                        }
+                        Some(_) => {
+                            return Err(Error::Other(format!("cannot detach database {}", db_name)))
+                        }
+                        None => return Err(Error::Other(format!("no such database: {}", db_name))),
+                    }
+                }
+                _ => {
+                    let mut warnings = vec![];
+                    if let Some(record) = maybe_record(stmt, &databases, options, &mut warnings)? {
+                        relations.push(record)
+                    }
+                    diagnostics.extend(
+                        warnings
+                            .into_iter()
+                            .map(|d| d.locate(&ddl, stmt_range.0, stmt_range.1)),
+                    );
                 }
             }
-            _ => {}
         }
     }
 
-    Ok(ret)
+    for (trigger, sql, _) in &triggers {
+        if let Stmt::CreateTrigger {
+            trigger_name,
+            tbl_name,
+            ..
+        } = trigger
+        {
+            let db = tbl_name
+                .db_name
+                .as_ref()
+                .or(trigger_name.db_name.as_ref())
+                .map(|n| util::unquote_ident(&n.0).to_string());
+            let tbl_name = util::unquote_ident(&tbl_name.name.0);
+            if let Some(relation) = indexed_relation(&mut relations, db, &tbl_name) {
+                relation.2.triggers.push(sql.to_string());
+            }
+        }
+    }
+
+    // Trigger bodies are resolved when they run so they can see everything the schema defines.
+    let schema = Schema::from_relations(relations.clone());
+    diagnostics.extend(triggers.iter().flat_map(|(trigger, _, (from, to))| {
+        triggers::check_trigger(trigger, &schema, options)
+            .into_iter()
+            .map(|d| d.locate(&ddl, *from, *to))
+    }));
+
+    Ok(SchemaShape {
+        relations,
+        diagnostics,
+    })
+}
+
+// A view is typed against the relations created before it.
+// Unaliased expressions are named after their text, like SQLite names the view's columns.
+fn view_columns(
+    select: &Select,
+    columns: &Option<Vec<IndexedColumn>>,
+    sql: &str,
+    relations: &[NamedRelation],
+    options: &Options,
+) -> Result<Vec<Col>, Error> {
+    let schema = Schema::from_relations(relations.to_vec());
+    let column_texts = spans::result_column_texts(sql);
+    let options = Options {
+        sqlite_column_names: true,
+        ..options.clone()
+    };
+    let ctx = Context {
+        schema: &schema,
+        options: &options,
+        ctes: &[],
+        column_texts: &column_texts,
+        diagnostics: None,
+        outermost: true,
+    };
+    let (_, mut cols) = queries::select_to_relation(select, &[], &ctx)?;
+    if let Some(columns) = columns {
+        if columns.len() != cols.len() {
+            return Err(Error::coded(
+                Code::ColumnCount,
+                format!("expected {} columns but got {}", columns.len(), cols.len()),
+            ));
+        }
+        for (col, column) in cols.iter_mut().zip(columns) {
+            col.0 = util::unquote_ident(&column.col_name.0).to_string();
+        }
+    }
+    Ok(cols)
+}
+
+// `ATTACH 'file.db' AS analytics` -> `analytics`
+fn database_name(expr: &Expr) -> Result<String, String> {
+    match expr {
+        Expr::Id(Id(name)) | Expr::Name(Name(name)) => Ok(util::unquote_ident(name).to_string()),
+        Expr::Literal(Literal::String(name)) => Ok(util::unquote_ident(name).to_string()),
+        _ => Err("database names must be identifiers".to_string()),
+    }
+}
+
+// Database names are case-insensitive. Returns the position of `name` in `databases`.
+fn find_database(databases: &[String], name: &str) -> Option<usize> {
+    databases.iter().position(|d| util::ident_eq(d, name))
+}
+
+// The `db.name` a created relation is registered under.
+// TEMP relations go into `temp`. Everything else goes into the database it is qualified with or `main`.
+// https://www.sqlite.org/lang_createtable.html
+fn relation_name(
+    tbl_name: &QualifiedName,
+    temporary: bool,
+    databases: &[String],
+) -> Result<RelationName, String> {
+    let name = util::unquote_ident(&tbl_name.name.0);
+    let db = match (&tbl_name.db_name, temporary) {
+        (None, false) => "main",
+        (None, true) => "temp",
+        (Some(Name(db)), temporary) => {
+            let db = util::unquote_ident(db);
+            if temporary && !util::ident_eq(&db, "temp") {
+                return Err(format!(
+                    "temporary table name must be unqualified: {}.{}",
+                    db, name
+                ));
+            }
+            match find_database(databases, &db) {
+                Some(i) => &databases[i],
+                None => return Err(format!("unknown database {}", db)),
+            }
+        }
+    };
+    Ok(format!("{}.{}", db, name))
 }
 
-fn maybe_record(stmt: Stmt) -> Result<Option<NamedRelation>, String> {
+fn maybe_record(
+    stmt: Stmt,
+    databases: &[String],
+    options: &Options,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Option<NamedRelation>, Error> {
     match stmt {
-        Stmt::CreateTable { tbl_name, body, .. } => Ok(Some((
-            format!("main.{}", util::unquote_ident(&tbl_name.name.0)),
-            get_properties(body)?,
-        ))),
+        Stmt::CreateTable {
+            temporary,
+            tbl_name,
+            body,
+            ..
+        } => {
+            let name = relation_name(&tbl_name, temporary, databases)?;
+            let tbl_name = util::unquote_ident(&tbl_name.name.0);
+            let info = get_info(&body);
+            Ok(Some((
+                name,
+                get_properties(&tbl_name, body, &info, options)?,
+                info,
+            )))
+        }
+        Stmt::CreateVirtualTable {
+            tbl_name,
+            module_name,
+            args,
+            ..
+        } => {
+            let name = relation_name(&tbl_name, false, databases)?;
+            let tbl_name = util::unquote_ident(&tbl_name.name.0);
+            let module = util::unquote_ident(&module_name.0).to_lowercase();
+            let args = args.unwrap_or_default();
+            // a module we don't know the shape of doesn't sink the rest of the schema. The table is left out.
+            let Some((mut cols, hidden)) =
+                virtual_table_columns(&tbl_name, &module, &args, options)?
+            else {
+                diagnostics.push(
+                    Diagnostic::new(
+                        Code::UnknownModule,
+                        Severity::Warning,
+                        format!(
+                            "unknown virtual table module {} for {}. Declare its shape in the `modules` option.",
+                            module, tbl_name
+                        ),
+                    )
+                    .about(module_name.0.clone()),
+                );
+                return Ok(None);
+            };
+            let info = RelationInfo {
+                module: Some(module),
+                hidden_columns: hidden.iter().map(|c| c.0.to_string()).collect(),
+                ..RelationInfo::default()
+            };
+            cols.extend(hidden);
+            Ok(Some((name, cols, info)))
+        }
         _ => Ok(None),
     }
 }
 
-fn get_properties(body: CreateTableBody) -> Result<Vec<Col>, String> {
+// The visible columns and the hidden columns of a virtual table
+type VirtualTableColumns = (Vec<Col>, Vec<Col>);
+
+// None for a module we don't know.
+fn virtual_table_columns(
+    tbl_name: &str,
+    module: &str,
+    args: &[String],
+    options: &Options,
+) -> Result<Option<VirtualTableColumns>, Error> {
+    match module {
+        "fts5" => Ok(Some(fts5_columns(tbl_name, args))),
+        "rtree" => rtree_columns(tbl_name, args, BuiltinType::Float, options).map(Some),
+        "rtree_i32" => rtree_columns(tbl_name, args, BuiltinType::Int, options).map(Some),
+        _ => Ok(options
+            .modules
+            .iter()
+            .find(|m| m.name.eq_ignore_ascii_case(module))
+            .map(|m| (m.columns.to_vec(), m.hidden_columns.to_vec()))),
+    }
+}
+
+// https://www.sqlite.org/fts5.html#fts5_table_creation_and_initialization
+// Every arg is either a column, optionally followed by UNINDEXED, or a `key = value` option.
+// FTS5 stores whatever it is given but the point of the table is text so that is what we say its columns hold.
+// The table also gets two hidden columns: one named after the table, used for MATCH, and `rank`.
+fn fts5_columns(tbl_name: &str, args: &[String]) -> VirtualTableColumns {
+    let cols = args
+        .iter()
+        .filter_map(|arg| {
+            let (name, rest) = split_leading_ident(arg);
+            if rest.starts_with('=') {
+                None
+            } else {
+                let mut t = builtin_type(BuiltinType::String);
+                t.extend(builtin_type(BuiltinType::Null));
+                Some((name.to_string(), t))
+            }
+        })
+        .collect();
+    let mut rank = builtin_type(BuiltinType::Float);
+    rank.extend(builtin_type(BuiltinType::Null));
+    let hidden = vec![
+        (tbl_name.to_string(), builtin_type(BuiltinType::Any)),
+        ("rank".to_string(), rank),
+    ];
+    (cols, hidden)
+}
+
+// https://www.sqlite.org/rtree.html#creating_an_r_tree_index
+// An integer id followed by min/max pairs for 1 to 5 dimensions, then any `+` prefixed auxiliary columns.
+fn rtree_columns(
+    tbl_name: &str,
+    args: &[String],
+    coordinate: BuiltinType,
+    options: &Options,
+) -> Result<VirtualTableColumns, Error> {
+    let (aux, coords): (Vec<_>, Vec<_>) = args.iter().partition(|a| a.trim().starts_with('+'));
+    let dimensions = coords.len().saturating_sub(1);
+    if coords.is_empty() || dimensions % 2 != 0 || !(2..=10).contains(&dimensions) {
+        return Err(Error::coded(
+            Code::ColumnCount,
+            format!("wrong number of columns for an rtree table: {}", tbl_name),
+        ));
+    }
+    let mut cols = coords
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let name = split_leading_ident(arg).0.to_string();
+            if i == 0 {
+                (name, builtin_type(BuiltinType::Int))
+            } else {
+                (name, builtin_type(coordinate.clone()))
+            }
+        })
+        .collect::<Vec<_>>();
+    cols.extend(aux.iter().map(|arg| {
+        let (name, declared) = split_leading_ident(arg.trim()[1..].trim_start());
+        // auxiliary columns are typed like those of an ordinary table
+        let mut t = match declared_type(declared) {
+            Some(declared) => type_from_declared_type(&declared, false, options),
+            None => builtin_type(BuiltinType::Unspecified),
+        };
+        t.extend(builtin_type(BuiltinType::Null));
+        (name.to_string(), t)
+    }));
+    Ok((cols, vec![]))
+}
+
+// The type name and size of a column declared as raw text, like an auxiliary R*Tree column.
+// `VARCHAR(20)` -> `VARCHAR` with a max size of 20. None when there is no type.
+fn declared_type(declared: &str) -> Option<Type> {
+    let (name, size) = match declared.split_once('(') {
+        Some((name, size)) => (name, Some(size.trim_end().trim_end_matches(')'))),
+        None => (declared, None),
+    };
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() {
+        return None;
+    }
+    let number = |n: &str| Box::new(Expr::Literal(Literal::Numeric(n.trim().to_string())));
+    let size = size.map(|size| match size.split_once(',') {
+        Some((size1, size2)) => TypeSize::TypeSize(number(size1), number(size2)),
+        None => TypeSize::MaxSize(number(size)),
+    });
+    Some(Type { name, size })
+}
+
+// Virtual table args come to us as raw text. Split off the identifier they start with.
+// `"my col" UNINDEXED` -> (`my col`, `UNINDEXED`)
+fn split_leading_ident(arg: &str) -> (Cow<'_, str>, &str) {
+    let arg = arg.trim();
+    let close = match arg.chars().next() {
+        Some('"') => Some('"'),
+        Some('`') => Some('`'),
+        Some('\'') => Some('\''),
+        Some('[') => Some(']'),
+        _ => None,
+    };
+    let end = match close {
+        // a doubled closing quote is an escaped quote rather than the end of the identifier
+        Some(close) => {
+            let mut chars = arg.char_indices().skip(1).peekable();
+            let mut end = arg.len();
+            while let Some((i, c)) = chars.next() {
+                if c == close {
+                    if chars.peek().map(|(_, c)| *c) == Some(close) {
+                        chars.next();
+                    } else {
+                        end = i + 1;
+                        break;
+                    }
+                }
+            }
+            end
+        }
+        None => arg
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(arg.len()),
+    };
+    let (name, rest) = arg.split_at(end);
+    (util::unquote_ident(name), rest.trim())
+}
+
+fn get_info(body: &CreateTableBody) -> RelationInfo {
+    match body {
+        CreateTableBody::AsSelect(_) => RelationInfo::default(),
+        CreateTableBody::ColumnsAndConstraints {
+            columns,
+            constraints,
+            options,
+        } => RelationInfo {
+            strict: options.contains(TableOptions::STRICT),
+            without_rowid: options.contains(TableOptions::WITHOUT_ROWID),
+            unique_keys: columns
+                .iter()
+                .filter(|c| {
+                    c.constraints.iter().any(|c| {
+                        matches!(
+                            c.constraint,
+                            ColumnConstraint::PrimaryKey { .. } | ColumnConstraint::Unique(_)
+                        )
+                    })
+                })
+                .map(|c| vec![util::unquote_ident(&c.col_name.0).to_string()])
+                .chain(
+                    constraints
+                        .iter()
+                        .flatten()
+                        .filter_map(|c| match &c.constraint {
+                            TableConstraint::PrimaryKey { columns, .. }
+                            | TableConstraint::Unique { columns, .. } => key_col_names(columns),
+                            _ => None,
+                        }),
+                )
+                .collect(),
+            declared_types: columns
+                .iter()
+                .map(|c| c.col_type.as_ref().map(|t| t.name.clone()))
+                .collect(),
+            ..RelationInfo::default()
+        },
+    }
+}
+
+// The columns of a key. None when it includes an expression since that doesn't make any column unique.
+fn key_col_names(columns: &[SortedColumn]) -> Option<Vec<ColName>> {
+    columns
+        .iter()
+        .map(|c| match &c.expr {
+            Expr::Id(Id(name)) | Expr::Name(Name(name)) => {
+                Some(util::unquote_ident(name).to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// The table an index is created on. It lives in the database the index is qualified with.
+// An unqualified index goes with a temp table of that name if there is one and main otherwise.
+fn indexed_relation<'a>(
+    relations: &'a mut [NamedRelation],
+    db: Option<String>,
+    tbl_name: &str,
+) -> Option<&'a mut NamedRelation> {
+    let dbs = match db {
+        Some(db) => vec![db],
+        None => vec!["temp".to_string(), "main".to_string()],
+    };
+    let i = dbs.iter().find_map(|db| {
+        relations.iter().position(|r| {
+            let (relation_db, name) = split_relation_name(&r.0);
+            util::ident_eq(relation_db, db) && util::ident_eq(name, tbl_name)
+        })
+    })?;
+    relations.get_mut(i)
+}
+
+fn get_properties(
+    tbl_name: &str,
+    body: CreateTableBody,
+    info: &RelationInfo,
+    options: &Options,
+) -> Result<Vec<Col>, Error> {
     // TODO: AsSelect would depend on things already defined.
     // We should allow access to that then so we can support AsSelect.
     // The data format should then match what JS provides us when asking for types on queries.
     match body {
-        CreateTableBody::AsSelect(_) => {
-            Err("Create table via Select is not yet supported".to_string())
+        CreateTableBody::AsSelect(_) => Err(Error::Other(
+            "Create table via Select is not yet supported".to_string(),
+        )),
+        CreateTableBody::ColumnsAndConstraints {
+            columns,
+            constraints,
+            ..
+        } => {
+            // table level checks can restrict any column
+            let table_checks = constraints
+                .iter()
+                .flatten()
+                .filter_map(|c| match &c.constraint {
+                    TableConstraint::Check(e) => Some(e),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if info.strict {
+                for column in &columns {
+                    check_strict_column(tbl_name, column)?;
+                }
+            }
+            Ok(columns
+                .into_iter()
+                .map(|c| column_as_property(c, &table_checks, info.strict, options))
+                .collect::<Vec<Col>>())
         }
-        CreateTableBody::ColumnsAndConstraints { columns, .. } => Ok(columns
-            .into_iter()
-            .map(|c| column_as_property(c))
-            .collect::<Vec<Col>>()),
+    }
+}
+
+// SQLite rejects these at create time. We do too so the user finds out before running the DDL.
+// https://www.sqlite.org/stricttables.html
+fn check_strict_column(tbl_name: &str, column: &ColumnDefinition) -> Result<(), Error> {
+    let col_name = util::unquote_ident(&column.col_name.0);
+    match &column.col_type {
+        None => Err(Error::coded(
+            Code::UnknownType,
+            format!("missing datatype for {}.{}", tbl_name, col_name),
+        )),
+        Some(t) if !is_strict_type_name(&t.name) => Err(Error::coded(
+            Code::UnknownType,
+            format!(
+                "unknown datatype for {}.{}: \"{}\"",
+                tbl_name, col_name, t.name
+            ),
+        )),
+        Some(_) => Ok(()),
     }
 }
 
 // Faithfully return types as specified. The layer above us (TS or Java or ...) will map to their native types.
-fn column_as_property(column: ColumnDefinition) -> Col {
+// Unless the user asked for storage types, in which case we return what SQLite will store.
+fn column_as_property(
+    column: ColumnDefinition,
+    table_checks: &[&Expr],
+    strict: bool,
+    options: &Options,
+) -> Col {
+    let col_name = util::unquote_ident(&column.col_name.0).to_string();
     let mut col_type = if let Some(col_type) = column.col_type {
-        type_from_type_name(col_type.name)
+        type_from_declared_type(&col_type, strict, options)
     } else {
         builtin_type(BuiltinType::Unspecified)
     };
-    if !column.constraints.iter().any(|c| match c.constraint {
-        ColumnConstraint::NotNull {
-            nullable: false, ..
-        } => true,
-        _ => false,
+    // A CHECK restricting the column to a set of literals narrows the declared type to a union of those literals.
+    // NULL passes any CHECK so nullability is decided below, same as for every other column.
+    if let Some(domain) = column
+        .constraints
+        .iter()
+        .filter_map(|c| match &c.constraint {
+            ColumnConstraint::Check(e) => Some(e),
+            _ => None,
+        })
+        .chain(table_checks.iter().copied())
+        .filter_map(|e| checks::literal_domain(&col_name, e))
+        .reduce(|acc, d| acc.into_iter().filter(|t| d.contains(t)).collect())
+    {
+        col_type = domain;
+    }
+    if !column.constraints.iter().any(|c| {
+        matches!(
+            c.constraint,
+            ColumnConstraint::NotNull {
+                nullable: false,
+                ..
+            }
+        )
     }) {
         // no not null constraint
         col_type.extend(builtin_type(BuiltinType::Null))
     }
-    (
-        util::unquote_ident(&column.col_name.0).to_string(),
-        col_type,
-    )
+    (col_name, col_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ModuleShape;
+
+    fn literal(s: &str) -> (TypeKind, Option<BuiltinType>, Option<String>) {
+        (
+            TypeKind::Literal,
+            Some(BuiltinType::String),
+            Some(s.to_string()),
+        )
+    }
+
+    #[test]
+    fn check_in_narrows_to_literals() {
+        let shapes = get_relation_shapes(
+            "CREATE TABLE issue (
+                status TEXT NOT NULL CHECK (status IN ('open','closed','archived')),
+                kind TEXT,
+                CHECK (kind = 'bug' OR kind = 'feature')
+            );"
+            .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            shapes,
+            vec![(
+                "main.issue".to_string(),
+                vec![
+                    (
+                        "status".to_string(),
+                        vec![
+                            literal("'open'"),
+                            literal("'closed'"),
+                            literal("'archived'")
+                        ]
+                    ),
+                    (
+                        "kind".to_string(),
+                        vec![
+                            literal("'bug'"),
+                            literal("'feature'"),
+                            (TypeKind::Builtin, Some(BuiltinType::Null), None)
+                        ]
+                    )
+                ],
+                RelationInfo {
+                    declared_types: vec![Some("TEXT".to_string()), Some("TEXT".to_string())],
+                    ..RelationInfo::default()
+                },
+            )]
+        )
+    }
+
+    #[test]
+    fn strict_and_without_rowid_are_detected() {
+        let shapes = get_relation_shapes(
+            "CREATE TABLE foo (a INTEGER PRIMARY KEY, b TEXT) STRICT, WITHOUT ROWID;
+            CREATE TABLE bar (a INTEGER);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            shapes.iter().map(|s| s.2.clone()).collect::<Vec<_>>(),
+            vec![
+                RelationInfo {
+                    strict: true,
+                    without_rowid: true,
+                    unique_keys: vec![vec!["a".to_string()]],
+                    declared_types: vec![Some("INTEGER".to_string()), Some("TEXT".to_string())],
+                    ..RelationInfo::default()
+                },
+                RelationInfo {
+                    declared_types: vec![Some("INTEGER".to_string())],
+                    ..RelationInfo::default()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn views_and_triggers() {
+        let shape = analyze_schema(
+            "CREATE TABLE t (a INTEGER NOT NULL, b TEXT);
+            CREATE VIEW v (x, y) AS SELECT a, b FROM t;
+            CREATE TEMP VIEW totals AS SELECT count(*), max(x) AS top FROM v;
+            CREATE VIEW broken AS SELECT c FROM t;
+            CREATE TRIGGER t_insert AFTER INSERT ON t BEGIN DELETE FROM t WHERE a < 0; END;"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let names = shape
+            .relations
+            .iter()
+            .map(|r| (r.0.as_str(), r.1.iter().map(|c| c.0.as_str()).collect()))
+            .collect::<Vec<(_, Vec<_>)>>();
+        assert_eq!(
+            names,
+            vec![
+                ("main.t", vec!["a", "b"]),
+                ("main.v", vec!["x", "y"]),
+                ("temp.totals", vec!["count(*)", "top"]),
+            ]
+        );
+        assert_eq!(
+            shape.relations[1].2.view.as_deref(),
+            Some("CREATE VIEW v (x, y) AS SELECT a, b FROM t;")
+        );
+        assert_eq!(
+            shape.relations[0].2.triggers,
+            vec![
+                "CREATE TRIGGER t_insert AFTER INSERT ON t BEGIN DELETE FROM t WHERE a < 0; END;"
+                    .to_string()
+            ]
+        );
+        assert_eq!(
+            shape
+                .diagnostics
+                .iter()
+                .map(|d| (d.code, d.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(
+                Code::NoSuchColumn,
+                "in view main.broken: no such column: c. Did you mean a or b?"
+            )]
+        );
+        // only the tables, like before views were typed
+        let relations = get_relation_shapes(
+            "CREATE TABLE t (a INTEGER); CREATE VIEW v AS SELECT a FROM t;".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            relations.iter().map(|r| r.0.as_str()).collect::<Vec<_>>(),
+            vec!["main.t"]
+        );
+    }
+
+    #[test]
+    fn unique_keys() {
+        let shapes = get_relation_shapes(
+            "CREATE TABLE foo (a INTEGER, b TEXT UNIQUE, c TEXT, d TEXT, PRIMARY KEY (a, c));
+            CREATE UNIQUE INDEX foo_d ON foo (d);
+            CREATE UNIQUE INDEX foo_c_lower ON foo (lower(c));
+            CREATE UNIQUE INDEX foo_b_d ON foo (b, d) WHERE d IS NOT NULL;"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            shapes[0].2.unique_keys,
+            vec![
+                vec!["b".to_string()],
+                vec!["a".to_string(), "c".to_string()],
+                vec!["d".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_rejects_unknown_types() {
+        assert_eq!(
+            get_relation_shapes(
+                "CREATE TABLE foo (a VARCHAR(20)) STRICT;".to_string(),
+                &Options::default()
+            ),
+            Err(Error::coded(
+                Code::UnknownType,
+                "unknown datatype for foo.a: \"VARCHAR\"".to_string()
+            ))
+        );
+        assert_eq!(
+            get_relation_shapes(
+                "CREATE TABLE foo (a) STRICT;".to_string(),
+                &Options::default()
+            ),
+            Err(Error::coded(
+                Code::UnknownType,
+                "missing datatype for foo.a".to_string()
+            ))
+        );
+        // the same types are fine outside of strict mode
+        assert!(get_relation_shapes(
+            "CREATE TABLE foo (a VARCHAR(20), b);".to_string(),
+            &Options::default()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn affinity_types() {
+        let shapes = get_relation_shapes(
+            "CREATE TABLE foo (
+                a VARCHAR(20) NOT NULL,
+                b DECIMAL(10, 5) NOT NULL,
+                c UNSIGNED BIG INT NOT NULL,
+                d DOUBLE PRECISION NOT NULL,
+                e BOOLEAN NOT NULL,
+                f NOT NULL
+            );"
+            .to_string(),
+            &Options {
+                affinity_types: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            shapes[0].1,
+            vec![
+                ("a".to_string(), builtin_type(BuiltinType::String)),
+                ("b".to_string(), builtin_type(BuiltinType::Number)),
+                ("c".to_string(), builtin_type(BuiltinType::Int)),
+                ("d".to_string(), builtin_type(BuiltinType::Float)),
+                ("e".to_string(), builtin_type(BuiltinType::Number)),
+                ("f".to_string(), builtin_type(BuiltinType::Unspecified)),
+            ]
+        );
+
+        // a STRICT table's ANY column stores values as given
+        let shapes = get_relation_shapes(
+            "CREATE TABLE s (a ANY NOT NULL) STRICT;".to_string(),
+            &Options {
+                affinity_types: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(shapes[0].1[0].1, builtin_type(BuiltinType::Any));
+    }
+
+    #[test]
+    fn user_type_mappings() {
+        let options = Options {
+            type_mappings: vec![
+                ("timestamp".to_string(), BuiltinType::Int),
+                ("UUID".to_string(), BuiltinType::String),
+                ("Money".to_string(), BuiltinType::BigInt),
+                ("VARCHAR(*)".to_string(), BuiltinType::String),
+                ("*A*A*A*A*A*B".to_string(), BuiltinType::Date),
+            ],
+            ..Default::default()
+        };
+        let long = "A".repeat(200);
+        let shapes = get_relation_shapes(
+            format!(
+                "CREATE TABLE foo (
+                a TIMESTAMP NOT NULL,
+                b uuid(36) NOT NULL,
+                c MONEY NOT NULL,
+                d varchar( 20 ) NOT NULL,
+                e VARCHAR NOT NULL,
+                f DATE NOT NULL,
+                g XAYAZAAAB NOT NULL,
+                h {long}C NOT NULL
+            );"
+            ),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            shapes[0].1,
+            vec![
+                ("a".to_string(), builtin_type(BuiltinType::Int)),
+                ("b".to_string(), builtin_type(BuiltinType::String)),
+                ("c".to_string(), builtin_type(BuiltinType::BigInt)),
+                ("d".to_string(), builtin_type(BuiltinType::String)),
+                // `VARCHAR(*)` needs a size to match so we fall back to the built-in rules
+                (
+                    "e".to_string(),
+                    vec![(TypeKind::Custom, None, Some("VARCHAR".to_string()))]
+                ),
+                ("f".to_string(), builtin_type(BuiltinType::Date)),
+                ("g".to_string(), builtin_type(BuiltinType::Date)),
+                // a pattern with many `*` doesn't take long to rule out
+                (
+                    "h".to_string(),
+                    vec![(TypeKind::Custom, None, Some(format!("{long}C")))]
+                ),
+            ]
+        );
+    }
+
+    fn nullable(t: BuiltinType) -> ColType {
+        let mut ret = builtin_type(t);
+        ret.extend(builtin_type(BuiltinType::Null));
+        ret
+    }
+
+    #[test]
+    fn fts5_tables() {
+        let shapes = get_relation_shapes(
+            "CREATE VIRTUAL TABLE docs USING fts5(title, \"body text\" UNINDEXED, tokenize = 'porter', prefix=2);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            shapes,
+            vec![(
+                "main.docs".to_string(),
+                vec![
+                    ("title".to_string(), nullable(BuiltinType::String)),
+                    ("body text".to_string(), nullable(BuiltinType::String)),
+                    ("docs".to_string(), builtin_type(BuiltinType::Any)),
+                    ("rank".to_string(), nullable(BuiltinType::Float)),
+                ],
+                RelationInfo {
+                    module: Some("fts5".to_string()),
+                    hidden_columns: vec!["docs".to_string(), "rank".to_string()],
+                    ..RelationInfo::default()
+                },
+            )]
+        );
+    }
+
+    #[test]
+    fn rtree_tables() {
+        let shapes = get_relation_shapes(
+            "CREATE VIRTUAL TABLE boxes USING rtree(id, minX, maxX, minY, maxY, +label TEXT);
+            CREATE VIRTUAL TABLE cells USING RTREE_I32(id, x0, x1);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            shapes[0].1,
+            vec![
+                ("id".to_string(), builtin_type(BuiltinType::Int)),
+                ("minX".to_string(), builtin_type(BuiltinType::Float)),
+                ("maxX".to_string(), builtin_type(BuiltinType::Float)),
+                ("minY".to_string(), builtin_type(BuiltinType::Float)),
+                ("maxY".to_string(), builtin_type(BuiltinType::Float)),
+                ("label".to_string(), nullable(BuiltinType::String)),
+            ]
+        );
+        assert_eq!(
+            shapes[1].1,
+            vec![
+                ("id".to_string(), builtin_type(BuiltinType::Int)),
+                ("x0".to_string(), builtin_type(BuiltinType::Int)),
+                ("x1".to_string(), builtin_type(BuiltinType::Int)),
+            ]
+        );
+        assert_eq!(shapes[1].2.module, Some("rtree_i32".to_string()));
+        assert!(get_relation_shapes(
+            "CREATE VIRTUAL TABLE bad USING rtree(id, minX);".to_string(),
+            &Options::default()
+        )
+        .is_err());
+
+        // auxiliary columns honor type mappings and affinity typing like ordinary columns
+        let sql = "CREATE VIRTUAL TABLE boxes USING rtree(id, x0, x1, +made TIMESTAMP, +note VARCHAR(20));";
+        let shapes = get_relation_shapes(
+            sql.to_string(),
+            &Options {
+                type_mappings: vec![("TIMESTAMP".to_string(), BuiltinType::Int)],
+                affinity_types: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            shapes[0].1[3..],
+            [
+                ("made".to_string(), nullable(BuiltinType::Int)),
+                ("note".to_string(), nullable(BuiltinType::String)),
+            ]
+        );
+    }
+
+    #[test]
+    fn registered_modules() {
+        let sql = "CREATE VIRTUAL TABLE people USING csv(filename = 'people.csv');".to_string();
+        // an unknown module leaves the table out with a warning rather than failing the schema
+        let shape =
+            analyze_schema(format!("CREATE TABLE t (a); {}", sql), &Options::default()).unwrap();
+        assert_eq!(
+            shape
+                .relations
+                .iter()
+                .map(|r| r.0.as_str())
+                .collect::<Vec<_>>(),
+            vec!["main.t"]
+        );
+        assert_eq!(
+            shape
+                .diagnostics
+                .iter()
+                .map(|d| (d.code, d.severity))
+                .collect::<Vec<_>>(),
+            vec![(Code::UnknownModule, Severity::Warning)]
+        );
+        let options = Options {
+            modules: vec![ModuleShape {
+                name: "CSV".to_string(),
+                columns: vec![("c0".to_string(), builtin_type(BuiltinType::String))],
+                hidden_columns: vec![],
+            }],
+            ..Default::default()
+        };
+        let shapes = get_relation_shapes(sql, &options).unwrap();
+        assert_eq!(
+            shapes[0].1,
+            vec![("c0".to_string(), builtin_type(BuiltinType::String))]
+        );
+    }
+
+    #[test]
+    fn databases() {
+        let shapes = get_relation_shapes(
+            "ATTACH 'analytics.db' AS analytics;
+            CREATE TABLE foo (a);
+            CREATE TEMP TABLE foo (b);
+            CREATE TABLE temp.bar (c);
+            CREATE TABLE Analytics.events (d);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            shapes.iter().map(|s| s.0.as_str()).collect::<Vec<_>>(),
+            vec!["main.foo", "temp.foo", "temp.bar", "analytics.events"]
+        );
+
+        assert_eq!(
+            get_relation_shapes(
+                "CREATE TABLE analytics.events (d);".to_string(),
+                &Options::default()
+            ),
+            Err(Error::Other("unknown database analytics".to_string()))
+        );
+        assert_eq!(
+            get_relation_shapes(
+                "ATTACH 'a.db' AS a; CREATE TABLE a.foo (x); DETACH a;".to_string(),
+                &Options::default()
+            ),
+            Ok(vec![])
+        );
+    }
 }
@@ -0,0 +1,660 @@
+use fallible_iterator::FallibleIterator;
+use sqlite3_parser::{
+    ast::{
+        As, Cmd, Expr, FromClause, GroupBy, Id, InsertBody, JoinConstraint, Name, OneSelect,
+        QualifiedName, ResultColumn, Select, SelectTable, Set, Stmt, TriggerCmd, TriggerEvent,
+        Upsert, UpsertDo, With,
+    },
+    lexer::sql::Parser,
+};
+
+use crate::diagnostics::{Code, Diagnostic, Severity};
+use crate::queries::Context;
+use crate::types::*;
+use crate::util;
+use crate::walk::walk_expr;
+
+// The relations a statement reads and writes, for invalidating live queries when something they read is written.
+// - subqueries and CTEs are followed into
+// - a view counts as read along with everything its select reads, following the views it reads in turn
+// - a write fires the relation's triggers. What their WHEN clauses and bodies read and write is included.
+//   Like SQLite without `recursive_triggers`, a trigger doesn't fire again from within itself.
+// Expressions of writes (WHERE, SET, RETURNING) read the target's columns the way SQLite's authorizer sees it.
+//
+// Columns are a superset. An unqualified name counts as a read of every table in its scope that has the column.
+pub fn dependencies(cmd: &Cmd, ctx: &Context) -> Dependencies {
+    let mut collector = Collector {
+        ctx,
+        ret: Dependencies::default(),
+        expanded: vec![],
+    };
+    // EXPLAIN doesn't run the statement
+    if let Cmd::Stmt(stmt) = cmd {
+        collector.stmt(stmt, &Scope::default());
+    }
+    collector.ret
+}
+
+// What names in an expression can refer to at some point in a statement
+#[derive(Clone, Default)]
+struct Scope {
+    // CTEs in scope. They shadow tables of the schema.
+    ctes: Vec<String>,
+    // The relations of each select we're within, innermost last
+    levels: Vec<Vec<ScopeRelation>>,
+}
+
+#[derive(Clone)]
+struct ScopeRelation {
+    // The name or alias the relation is referred to by
+    name: String,
+    // The schema relation it is, if it is one, as `db.name` and its columns
+    table: Option<(RelationName, Vec<ColName>)>,
+}
+
+impl Scope {
+    fn with_level(&self, level: Vec<ScopeRelation>) -> Scope {
+        let mut ret = self.clone();
+        ret.levels.push(level);
+        ret
+    }
+}
+
+enum Event {
+    Insert,
+    Update,
+    Delete,
+}
+
+struct Collector<'a> {
+    ctx: &'a Context<'a>,
+    ret: Dependencies,
+    // Views and triggers already followed
+    expanded: Vec<String>,
+}
+
+impl Collector<'_> {
+    fn stmt(&mut self, stmt: &Stmt, scope: &Scope) {
+        match stmt {
+            Stmt::Select(select) => self.select(select, scope),
+            Stmt::Insert {
+                with,
+                tbl_name,
+                columns,
+                body,
+                returning,
+                ..
+            } => {
+                let scope = self.with(with, scope);
+                let (select, upsert) = match body {
+                    InsertBody::Select(select, upsert) => (Some(select), upsert),
+                    InsertBody::DefaultValues => (None, &None),
+                };
+                let target = self.schema_relation(tbl_name.db_name.as_ref(), &tbl_name.name, None);
+                self.insert(target, columns, select, upsert, returning, &scope);
+            }
+            Stmt::Update {
+                with,
+                tbl_name,
+                sets,
+                from,
+                where_clause,
+                returning,
+                ..
+            } => {
+                let scope = self.with(with, scope);
+                let target = self.schema_relation(tbl_name.db_name.as_ref(), &tbl_name.name, None);
+                self.update(target, sets, from, where_clause, returning, &scope);
+            }
+            Stmt::Delete {
+                with,
+                tbl_name,
+                where_clause,
+                returning,
+                ..
+            } => {
+                let scope = self.with(with, scope);
+                let target = self.schema_relation(tbl_name.db_name.as_ref(), &tbl_name.name, None);
+                self.delete(target, where_clause, returning, &scope);
+            }
+            _ => {}
+        }
+    }
+
+    fn insert(
+        &mut self,
+        target: ScopeRelation,
+        columns: &Option<Vec<Name>>,
+        select: Option<&Select>,
+        upsert: &Option<Upsert>,
+        returning: &Option<Vec<ResultColumn>>,
+        scope: &Scope,
+    ) {
+        if let Some(select) = select {
+            self.select(select, scope);
+        }
+        let written = match columns {
+            Some(columns) => columns
+                .iter()
+                .map(|Name(name)| util::unquote_ident(name).to_string())
+                .collect(),
+            None => self.visible_cols(&target),
+        };
+        let excluded = ScopeRelation {
+            name: "excluded".to_string(),
+            table: None,
+        };
+        let inner = scope.with_level(vec![target.clone(), excluded]);
+        let mut upsert = upsert.as_ref();
+        while let Some(Upsert {
+            index,
+            do_clause,
+            next,
+        }) = upsert
+        {
+            for e in index.iter().flat_map(|i| &i.where_clause) {
+                self.expr(e, &inner);
+            }
+            if let UpsertDo::Set { sets, where_clause } = do_clause {
+                self.sets(sets, &inner);
+                self.opt_expr(where_clause, &inner);
+                self.write(&target, set_col_names(sets), Event::Update);
+            }
+            upsert = next.as_deref();
+        }
+        self.returning(returning, &inner);
+        self.write(&target, written, Event::Insert);
+    }
+
+    fn update(
+        &mut self,
+        target: ScopeRelation,
+        sets: &[Set],
+        from: &Option<FromClause>,
+        where_clause: &Option<Expr>,
+        returning: &Option<Vec<ResultColumn>>,
+        scope: &Scope,
+    ) {
+        let mut level = vec![target.clone()];
+        if let Some(from) = from {
+            level.extend(self.from(from, scope));
+        }
+        let inner = scope.with_level(level);
+        self.sets(sets, &inner);
+        self.opt_expr(where_clause, &inner);
+        self.returning(returning, &inner);
+        self.write(&target, set_col_names(sets), Event::Update);
+    }
+
+    fn delete(
+        &mut self,
+        target: ScopeRelation,
+        where_clause: &Option<Expr>,
+        returning: &Option<Vec<ResultColumn>>,
+        scope: &Scope,
+    ) {
+        let inner = scope.with_level(vec![target.clone()]);
+        self.opt_expr(where_clause, &inner);
+        self.returning(returning, &inner);
+        let written = target
+            .table
+            .as_ref()
+            .map(|(_, cols)| cols.clone())
+            .unwrap_or_default();
+        self.write(&target, written, Event::Delete);
+    }
+
+    fn sets(&mut self, sets: &[Set], scope: &Scope) {
+        for set in sets {
+            self.expr(&set.expr, scope);
+        }
+    }
+
+    fn returning(&mut self, returning: &Option<Vec<ResultColumn>>, scope: &Scope) {
+        for column in returning.iter().flatten() {
+            self.result_column(column, &scope.levels[scope.levels.len() - 1], scope);
+        }
+    }
+
+    // Record a write of `cols` and follow the triggers it fires
+    fn write(&mut self, target: &ScopeRelation, cols: Vec<ColName>, event: Event) {
+        let Some((name, _)) = &target.table else {
+            return;
+        };
+        push(&mut self.ret.writes, name.to_string());
+        if self.ctx.options.column_dependencies {
+            for col in &cols {
+                push(
+                    &mut self.ret.write_columns,
+                    (name.to_string(), col.to_string()),
+                );
+            }
+        }
+
+        let (db, table) = split_relation_name(name);
+        let Some((_, (_, info))) = self.ctx.schema.get(Some(db), table) else {
+            return;
+        };
+        for sql in info.triggers.clone() {
+            let what = format!("a trigger on {}", name);
+            let Some(Stmt::CreateTrigger {
+                trigger_name,
+                event: trigger_event,
+                when_clause,
+                commands,
+                ..
+            }) = self.recorded_stmt(&what, &sql, "CREATE TRIGGER")
+            else {
+                continue;
+            };
+            let fires = match (&event, &trigger_event) {
+                (Event::Insert, TriggerEvent::Insert)
+                | (Event::Delete, TriggerEvent::Delete)
+                | (Event::Update, TriggerEvent::Update) => true,
+                (Event::Update, TriggerEvent::UpdateOf(of)) => of.iter().any(|Name(c)| {
+                    let c = util::unquote_ident(c);
+                    cols.iter().any(|u| util::ident_eq(u, &c))
+                }),
+                _ => false,
+            };
+            let key = format!(
+                "trigger {}.{}",
+                db,
+                util::unquote_ident(&trigger_name.name.0)
+            );
+            if !fires || self.expanded.iter().any(|e| util::ident_eq(e, &key)) {
+                continue;
+            }
+            self.expanded.push(key);
+
+            let pseudo = ["NEW", "OLD"].map(|name| ScopeRelation {
+                name: name.to_string(),
+                table: None,
+            });
+            let scope = Scope::default().with_level(pseudo.to_vec());
+            self.opt_expr(&when_clause, &scope);
+            for command in &commands {
+                self.trigger_command(command, &scope);
+            }
+        }
+    }
+
+    fn trigger_command(&mut self, command: &TriggerCmd, scope: &Scope) {
+        match command {
+            TriggerCmd::Insert {
+                tbl_name,
+                col_names,
+                select,
+                upsert,
+                returning,
+                ..
+            } => {
+                let target = self.schema_relation(None, tbl_name, None);
+                self.insert(target, col_names, Some(select), upsert, returning, scope);
+            }
+            TriggerCmd::Update {
+                tbl_name,
+                sets,
+                from,
+                where_clause,
+                ..
+            } => {
+                let target = self.schema_relation(None, tbl_name, None);
+                self.update(target, sets, from, where_clause, &None, scope);
+            }
+            TriggerCmd::Delete {
+                tbl_name,
+                where_clause,
+            } => {
+                let target = self.schema_relation(None, tbl_name, None);
+                self.delete(target, where_clause, &None, scope);
+            }
+            TriggerCmd::Select(select) => self.select(select, scope),
+        }
+    }
+
+    fn with(&mut self, with: &Option<With>, scope: &Scope) -> Scope {
+        let mut ret = scope.clone();
+        for cte in with.iter().flat_map(|with| &with.ctes) {
+            // a recursive CTE refers to itself
+            ret.ctes
+                .push(util::unquote_ident(&cte.tbl_name.0).to_string());
+            self.select(&cte.select, &ret);
+        }
+        ret
+    }
+
+    fn select(&mut self, select: &Select, scope: &Scope) {
+        let scope = self.with(&select.with, scope);
+        let level = self.one_select(&select.body.select, &scope);
+        for compound in select.body.compounds.iter().flatten() {
+            self.one_select(&compound.select, &scope);
+        }
+        // ORDER BY can refer to the columns of the first select's relations
+        let inner = scope.with_level(level);
+        for term in select.order_by.iter().flatten() {
+            self.expr(&term.expr, &inner);
+        }
+        if let Some(limit) = &select.limit {
+            self.expr(&limit.expr, &scope);
+            self.opt_expr(&limit.offset, &scope);
+        }
+    }
+
+    // Returns the relations the select brings into scope
+    fn one_select(&mut self, select: &OneSelect, scope: &Scope) -> Vec<ScopeRelation> {
+        match select {
+            OneSelect::Select {
+                columns,
+                from,
+                where_clause,
+                group_by,
+                window_clause,
+                ..
+            } => {
+                let level = match from {
+                    Some(from) => self.from(from, scope),
+                    None => vec![],
+                };
+                let inner = scope.with_level(level.clone());
+                for join in from.iter().flat_map(|from| from.joins.iter().flatten()) {
+                    match &join.constraint {
+                        Some(JoinConstraint::On(e)) => self.expr(e, &inner),
+                        Some(JoinConstraint::Using(names)) => {
+                            for Name(name) in names {
+                                self.column(None, &util::unquote_ident(name), &inner);
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                for column in columns {
+                    self.result_column(column, &level, &inner);
+                }
+                self.opt_expr(where_clause, &inner);
+                if let Some(GroupBy { exprs, having }) = group_by {
+                    for e in exprs {
+                        self.expr(e, &inner);
+                    }
+                    if let Some(having) = having {
+                        self.expr(having, &inner);
+                    }
+                }
+                for def in window_clause.iter().flatten() {
+                    for e in def.window.partition_by.iter().flatten() {
+                        self.expr(e, &inner);
+                    }
+                    for term in def.window.order_by.iter().flatten() {
+                        self.expr(&term.expr, &inner);
+                    }
+                }
+                level
+            }
+            OneSelect::Values(rows) => {
+                for e in rows.iter().flatten() {
+                    self.expr(e, scope);
+                }
+                vec![]
+            }
+        }
+    }
+
+    fn result_column(&mut self, column: &ResultColumn, level: &[ScopeRelation], scope: &Scope) {
+        match column {
+            ResultColumn::Expr(e, _) => self.expr(e, scope),
+            ResultColumn::Star => {
+                for relation in level {
+                    self.all_columns(relation);
+                }
+            }
+            ResultColumn::TableStar(Name(table)) => {
+                let table = util::unquote_ident(table);
+                if let Some(relation) = level.iter().find(|r| util::ident_eq(&r.name, &table)) {
+                    self.all_columns(relation);
+                }
+            }
+        }
+    }
+
+    fn from(&mut self, from: &FromClause, scope: &Scope) -> Vec<ScopeRelation> {
+        from.select
+            .iter()
+            .map(|table| table.as_ref())
+            .chain(from.joins.iter().flatten().map(|join| &join.table))
+            .flat_map(|table| self.select_table(table, scope))
+            .collect()
+    }
+
+    fn select_table(&mut self, table: &SelectTable, scope: &Scope) -> Vec<ScopeRelation> {
+        match table {
+            SelectTable::Table(QualifiedName { db_name, name, .. }, maybe_as, _) => {
+                vec![self.table(db_name.as_ref(), name, maybe_as, scope)]
+            }
+            SelectTable::TableCall(QualifiedName { db_name, name, .. }, args, maybe_as) => {
+                for e in args.iter().flatten() {
+                    self.expr(e, scope);
+                }
+                vec![self.table(db_name.as_ref(), name, maybe_as, scope)]
+            }
+            SelectTable::Select(select, maybe_as) => {
+                self.select(select, scope);
+                vec![ScopeRelation {
+                    name: maybe_as.as_ref().map(alias).unwrap_or_default(),
+                    table: None,
+                }]
+            }
+            SelectTable::Sub(from, _) => self.from(from, scope),
+        }
+    }
+
+    // A table named in a FROM clause. Reading a view reads what the view reads.
+    fn table(
+        &mut self,
+        db: Option<&Name>,
+        name: &Name,
+        maybe_as: &Option<As>,
+        scope: &Scope,
+    ) -> ScopeRelation {
+        let alias = maybe_as.as_ref().map(alias);
+        let unquoted = util::unquote_ident(&name.0);
+        if db.is_none() && scope.ctes.iter().any(|c| util::ident_eq(c, &unquoted)) {
+            return ScopeRelation {
+                name: alias.unwrap_or(unquoted.to_string()),
+                table: None,
+            };
+        }
+        let relation = self.schema_relation(db, name, alias);
+        let Some((relation_name, _)) = &relation.table else {
+            return relation;
+        };
+        self.read(relation_name, &[]);
+
+        let (db, table) = split_relation_name(relation_name);
+        let key = format!("view {}", relation_name);
+        if let Some((
+            _,
+            (
+                _,
+                RelationInfo {
+                    view: Some(sql), ..
+                },
+            ),
+        )) = self.ctx.schema.get(Some(db), table)
+        {
+            if !self.expanded.contains(&key) {
+                self.expanded.push(key);
+                let what = format!("view {}", relation_name);
+                if let Some(Stmt::CreateView { select, .. }) =
+                    self.recorded_stmt(&what, sql, "CREATE VIEW")
+                {
+                    self.select(&select, &Scope::default());
+                }
+            }
+        }
+        relation
+    }
+
+    // The statement the schema recorded for a view or trigger. One that doesn't parse, or isn't the
+    // statement it should be, is reported since what it reads and writes can't be followed.
+    fn recorded_stmt(&mut self, what: &str, sql: &str, expected: &str) -> Option<Stmt> {
+        let is_expected = |stmt: &Stmt| match stmt {
+            Stmt::CreateView { .. } => expected == "CREATE VIEW",
+            Stmt::CreateTrigger { .. } => expected == "CREATE TRIGGER",
+            _ => false,
+        };
+        let reason = match Parser::new(sql.as_bytes()).next() {
+            Ok(Some(Cmd::Stmt(stmt))) if is_expected(&stmt) => return Some(stmt),
+            Ok(_) => format!("its SQL is not a {} statement", expected),
+            Err(e) => e.to_string(),
+        };
+        self.ret.diagnostics.push(Diagnostic::new(
+            Code::Syntax,
+            Severity::Warning,
+            format!("can't follow {}: {}", what, reason),
+        ));
+        None
+    }
+
+    // The relation of the schema `db.name` refers to, known in the statement by `alias` or its name
+    fn schema_relation(
+        &self,
+        db: Option<&Name>,
+        name: &Name,
+        alias: Option<String>,
+    ) -> ScopeRelation {
+        let db = db.map(|Name(db)| util::unquote_ident(db));
+        let name = util::unquote_ident(&name.0);
+        let table = self
+            .ctx
+            .schema
+            .get(db.as_deref(), &name)
+            .map(|(db, (cols, _))| {
+                let (db, name) = self
+                    .ctx
+                    .schema
+                    .declared_name(db, &name)
+                    .unwrap_or((db, &name));
+                (
+                    format!("{}.{}", db, name),
+                    cols.iter().map(|c| c.0.to_string()).collect(),
+                )
+            });
+        ScopeRelation {
+            name: alias.unwrap_or(name.to_string()),
+            table,
+        }
+    }
+
+    // The columns an INSERT without a column list writes. Hidden columns of virtual tables are left out.
+    fn visible_cols(&self, relation: &ScopeRelation) -> Vec<ColName> {
+        let Some((name, cols)) = &relation.table else {
+            return vec![];
+        };
+        let (db, table) = split_relation_name(name);
+        let hidden = self
+            .ctx
+            .schema
+            .get(Some(db), table)
+            .map(|(_, (_, info))| info.hidden_columns.clone())
+            .unwrap_or_default();
+        cols.iter()
+            .filter(|c| !hidden.iter().any(|h| util::ident_eq(h, c)))
+            .cloned()
+            .collect()
+    }
+
+    fn opt_expr(&mut self, e: &Option<Expr>, scope: &Scope) {
+        if let Some(e) = e {
+            self.expr(e, scope);
+        }
+    }
+
+    fn expr(&mut self, e: &Expr, scope: &Scope) {
+        let mut col_refs = vec![];
+        let mut selects = vec![];
+        let mut tables = vec![];
+        walk_expr(e, &mut |e| match e {
+            Expr::Id(Id(name)) | Expr::Name(Name(name)) => col_refs.push((None, name)),
+            Expr::Qualified(Name(table), Name(name))
+            | Expr::DoublyQualified(_, Name(table), Name(name)) => {
+                col_refs.push((Some(table), name))
+            }
+            Expr::Subquery(select) | Expr::Exists(select) | Expr::InSelect { rhs: select, .. } => {
+                selects.push(select)
+            }
+            Expr::InTable { rhs, .. } => tables.push(rhs),
+            _ => {}
+        });
+        for (table, name) in col_refs {
+            let table = table.map(|t| util::unquote_ident(t));
+            self.column(table.as_deref(), &util::unquote_ident(name), scope);
+        }
+        for select in selects {
+            self.select(select, scope);
+        }
+        // `x IN t` reads all of t's single column
+        for QualifiedName { db_name, name, .. } in tables {
+            let relation = self.table(db_name.as_ref(), name, &None, scope);
+            self.all_columns(&relation);
+        }
+    }
+
+    // Record a read of the column `table.name`, or `name` from whichever tables in scope have it
+    fn column(&mut self, table: Option<&str>, name: &str, scope: &Scope) {
+        for level in scope.levels.iter().rev() {
+            let mut found = false;
+            for relation in level {
+                if table.is_some_and(|t| !util::ident_eq(t, &relation.name)) {
+                    continue;
+                }
+                if let Some((relation_name, cols)) = &relation.table {
+                    if let Some(col) = cols.iter().find(|c| util::ident_eq(c, name)) {
+                        self.read(relation_name, std::slice::from_ref(col));
+                        found = true;
+                    }
+                }
+            }
+            if found {
+                return;
+            }
+        }
+    }
+
+    fn all_columns(&mut self, relation: &ScopeRelation) {
+        if let Some((relation_name, cols)) = &relation.table {
+            self.read(relation_name, cols);
+        }
+    }
+
+    // Record a read of `cols` of a relation. The target of a write is only read through its columns.
+    fn read(&mut self, relation_name: &str, cols: &[ColName]) {
+        push(&mut self.ret.reads, relation_name.to_string());
+        if self.ctx.options.column_dependencies {
+            for col in cols {
+                push(
+                    &mut self.ret.read_columns,
+                    (relation_name.to_string(), col.to_string()),
+                );
+            }
+        }
+    }
+}
+
+fn set_col_names(sets: &[Set]) -> Vec<ColName> {
+    sets.iter()
+        .flat_map(|set| &set.col_names)
+        .map(|Name(name)| util::unquote_ident(name).to_string())
+        .collect()
+}
+
+fn alias(as_: &As) -> String {
+    match as_ {
+        As::As(Name(name)) | As::Elided(Name(name)) => util::unquote_ident(name).to_string(),
+    }
+}
+
+fn push<T: PartialEq>(items: &mut Vec<T>, item: T) {
+    if !items.contains(&item) {
+        items.push(item);
+    }
+}
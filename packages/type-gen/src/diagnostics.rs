@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+use sqlite3_parser::lexer::sql::{self, ParserError};
+
+use crate::error::Error;
+use crate::spans;
+use crate::types::Span;
+
+// A problem found while analyzing a schema or query. Some stop us from producing a shape, others don't.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    pub code: Code,
+    pub severity: Severity,
+    pub message: String,
+    // Where the problem is in the SQL that was analyzed. None until located.
+    pub span: Option<Span>,
+    // Other places that explain the problem. E.g., each table an ambiguous column could be from.
+    pub related: Vec<Related>,
+    pub fixes: Vec<Fix>,
+    // The name or expression the problem is about as the SQL spells it, which `locate` points the span at
+    #[serde(skip)]
+    pub(crate) subject: Option<String>,
+    // What the subject could be instead, which `locate` turns into fixes
+    #[serde(skip)]
+    pub(crate) candidates: Vec<String>,
+}
+
+// What kind of problem a diagnostic is about. These don't change between versions so tools can match on them.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Code {
+    Syntax,
+    NoSuchTable,
+    NoSuchColumn,
+    AmbiguousColumn,
+    UnaliasedExpression,
+    DuplicateColumn,
+    OutOfRange,
+    JoinColumn,
+    ColumnCount,
+    UnknownType,
+    UnknownModule,
+    Unresolved,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Severity {
+    Error,
+    #[default]
+    Warning,
+    Hint,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Related {
+    pub span: Span,
+    pub message: String,
+}
+
+// A change to the SQL that would fix the problem, like correcting a misspelled name.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Fix {
+    pub message: String,
+    pub edits: Vec<Edit>,
+}
+
+// Replace the text of `span` with `text`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+impl Diagnostic {
+    pub fn new(code: Code, severity: Severity, message: String) -> Self {
+        Diagnostic {
+            code,
+            severity,
+            message,
+            span: None,
+            related: vec![],
+            fixes: vec![],
+            subject: None,
+            candidates: vec![],
+        }
+    }
+
+    pub fn about(self, subject: String) -> Self {
+        Diagnostic {
+            subject: Some(subject),
+            ..self
+        }
+    }
+
+    // Says where the problem was found, like the view or trigger it's in
+    pub fn within(self, context: &str) -> Self {
+        Diagnostic {
+            message: format!("{}: {}", context, self.message),
+            ..self
+        }
+    }
+
+    // A syntax error from the parser, pointing at the token it stopped at.
+    pub fn syntax(error: &sql::Error, sql: &str) -> Self {
+        let at =
+            |pos: &Option<(u64, usize)>| pos.map(|(line, col)| spans::offset_of(sql, line, col));
+        let (message, start, end) = match error {
+            // the position is just past the token the parser didn't expect
+            sql::Error::ParserError(
+                ParserError::SyntaxError {
+                    found: Some(found), ..
+                },
+                pos,
+            ) => {
+                let end = at(pos).unwrap_or(sql.len());
+                let start = end
+                    .checked_sub(found.len())
+                    .filter(|&start| {
+                        sql.get(start..end)
+                            .is_some_and(|t| t.eq_ignore_ascii_case(found))
+                    })
+                    .unwrap_or(end);
+                (format!("near \"{}\": syntax error", found), start, end)
+            }
+            sql::Error::ParserError(ParserError::SyntaxError { found: None, .. }, _)
+            | sql::Error::ParserError(ParserError::UnexpectedEof, _) => {
+                ("incomplete input".to_string(), sql.len(), sql.len())
+            }
+            sql::Error::ParserError(e, pos) => {
+                let end = at(pos).unwrap_or(sql.len());
+                (e.to_string(), end, end)
+            }
+            // the rest come from the tokenizer, which points at where the bad token starts
+            sql::Error::UnterminatedLiteral(pos)
+            | sql::Error::UnterminatedBracket(pos)
+            | sql::Error::UnterminatedBlockComment(pos) => {
+                let message = match error {
+                    sql::Error::UnterminatedLiteral(_) => "unterminated literal",
+                    sql::Error::UnterminatedBracket(_) => "unterminated bracket",
+                    _ => "unterminated block comment",
+                };
+                (message.to_string(), at(pos).unwrap_or(0), sql.len())
+            }
+            sql::Error::Io(e) => (e.to_string(), 0, 0),
+            e => {
+                let start = match e {
+                    sql::Error::UnrecognizedToken(pos)
+                    | sql::Error::BadVariableName(pos)
+                    | sql::Error::BadNumber(pos)
+                    | sql::Error::ExpectedEqualsSign(pos)
+                    | sql::Error::MalformedBlobLiteral(pos)
+                    | sql::Error::MalformedHexInteger(pos) => at(pos).unwrap_or(0),
+                    _ => 0,
+                };
+                // the message ends with the position, which the span replaces
+                let message = e.to_string();
+                let message = message
+                    .rsplit_once(" at ")
+                    .map_or(message.as_str(), |(m, _)| m);
+                let end = sql[start..]
+                    .find(char::is_whitespace)
+                    .map_or(sql.len(), |n| start + n)
+                    .max(start + sql[start..].chars().next().map_or(0, char::len_utf8));
+                (message.to_string(), start, end)
+            }
+        };
+        Diagnostic {
+            span: Some(spans::span(sql, start, end)),
+            ..Diagnostic::new(Code::Syntax, Severity::Error, message)
+        }
+    }
+
+    // Points the diagnostic at the part of the statement between `from` and `to` it is about,
+    // or at the whole statement when that can't be told.
+    pub fn locate(mut self, sql: &str, from: usize, to: usize) -> Self {
+        if self.span.is_some() {
+            return self;
+        }
+        let subject = self.subject.clone();
+        let found = subject
+            .as_deref()
+            .and_then(|subject| spans::find_tokens(sql, from, to, subject));
+        let Some((start, last, end)) = found else {
+            self.span = Some(spans::statement_span(sql, from, to));
+            return self;
+        };
+        self.span = Some(spans::span(sql, start, end));
+
+        match self.code {
+            // the suggestions replace the misspelled name, not what qualifies it
+            Code::NoSuchTable | Code::NoSuchColumn => {
+                self.fixes = self
+                    .candidates
+                    .iter()
+                    .map(|suggestion| Fix {
+                        message: format!("Change to {}", suggestion),
+                        edits: vec![Edit {
+                            span: spans::span(sql, last, end),
+                            text: suggestion.to_string(),
+                        }],
+                    })
+                    .collect();
+            }
+            Code::AmbiguousColumn => {
+                for candidate in self.candidates.clone() {
+                    let relation = candidate.rsplit_once('.').map_or("", |(r, _)| r);
+                    if let Some((r_start, _, r_end)) = spans::find_tokens(sql, from, to, relation) {
+                        self.related.push(Related {
+                            span: spans::span(sql, r_start, r_end),
+                            message: format!(
+                                "{} has a column named {}",
+                                relation,
+                                subject.as_deref().unwrap_or_default()
+                            ),
+                        });
+                    }
+                    self.fixes.push(Fix {
+                        message: format!("Qualify as {}", candidate),
+                        edits: vec![Edit {
+                            span: spans::span(sql, start, end),
+                            text: candidate,
+                        }],
+                    });
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
+// An error that stopped the analysis of a statement, as a diagnostic.
+impl From<Error> for Diagnostic {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Parse(message) => Diagnostic::new(Code::Syntax, Severity::Error, message),
+            Error::Other(message) => Diagnostic::new(Code::Other, Severity::Error, message),
+            Error::Coded {
+                code,
+                message,
+                subject,
+                candidates,
+            } => Diagnostic {
+                subject,
+                candidates,
+                ..Diagnostic::new(code, Severity::Error, message)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fallible_iterator::FallibleIterator;
+    use sqlite3_parser::lexer::sql::Parser;
+
+    fn syntax(sql: &str) -> (String, usize, usize) {
+        let mut parser = Parser::new(sql.as_bytes());
+        let error = loop {
+            if let Err(e) = parser.next() {
+                break e;
+            }
+        };
+        let diagnostic = Diagnostic::syntax(&error, sql);
+        let span = diagnostic.span.unwrap();
+        (diagnostic.message, span.start.offset, span.end.offset)
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            syntax("SELECT 1; SELEC a FROM t;"),
+            ("near \"SELEC\": syntax error".to_string(), 10, 15)
+        );
+        assert_eq!(
+            syntax("SELECT a FROM"),
+            ("incomplete input".to_string(), 13, 13)
+        );
+        assert_eq!(
+            syntax("SELECT 'abc"),
+            ("unterminated literal".to_string(), 7, 11)
+        );
+    }
+
+    #[test]
+    fn locating() {
+        let sql = "SELECT 1; SELECT nmae FROM users";
+        let d = Diagnostic::from(Error::no_such_column(None, "nmae", ["name", "email"])).locate(
+            sql,
+            9,
+            sql.len(),
+        );
+        assert_eq!(d.code, Code::NoSuchColumn);
+        assert_eq!(d.span, Some(spans::span(sql, 17, 21)));
+        assert_eq!(
+            d.fixes
+                .iter()
+                .map(|f| f.edits[0].text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["name"]
+        );
+
+        let sql = "SELECT id FROM a, b";
+        let d = Diagnostic::from(Error::ambiguous_column(
+            "id",
+            vec!["a.id".to_string(), "b.id".to_string()],
+        ))
+        .locate(sql, 0, sql.len());
+        assert_eq!(d.span, Some(spans::span(sql, 7, 9)));
+        assert_eq!(
+            d.related
+                .iter()
+                .map(|r| r.span.start.offset)
+                .collect::<Vec<_>>(),
+            vec![15, 18]
+        );
+        assert_eq!(d.fixes[1].edits[0].text, "b.id");
+
+        let d = Diagnostic::from(Error::coded(
+            Code::ColumnCount,
+            "table x has 2 values for 3 columns".to_string(),
+        ))
+        .locate(sql, 0, sql.len());
+        assert_eq!(d.code, Code::ColumnCount);
+        assert_eq!(d.span, Some(spans::statement_span(sql, 0, sql.len())));
+
+        // The code comes from where the error was made, not from words in the message
+        let d = Diagnostic::new(
+            Code::Other,
+            Severity::Error,
+            "no such column: id".to_string(),
+        )
+        .locate(sql, 0, sql.len());
+        assert_eq!(d.code, Code::Other);
+        assert!(d.fixes.is_empty());
+    }
+}
@@ -1,9 +1,120 @@
 use sqlite3_parser::lexer::sql;
 
+use crate::diagnostics::Code;
+use crate::util;
+
 #[derive(PartialEq, Debug)]
 pub enum Error {
     Parse(String),
     Other(String),
+    // A problem tools can tell apart by its code. `subject` is the name or expression it is about as the SQL
+    // spells it. `candidates` are what the subject could be instead: names close to one that doesn't exist,
+    // or the qualified columns an ambiguous name could be.
+    Coded {
+        code: Code,
+        message: String,
+        subject: Option<String>,
+        candidates: Vec<String>,
+    },
+}
+
+impl Error {
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Parse(message) | Error::Other(message) | Error::Coded { message, .. } => message,
+        }
+    }
+
+    pub fn coded(code: Code, message: String) -> Self {
+        Error::Coded {
+            code,
+            message,
+            subject: None,
+            candidates: vec![],
+        }
+    }
+
+    pub fn about(code: Code, message: String, subject: String) -> Self {
+        Error::Coded {
+            code,
+            message,
+            subject: Some(subject),
+            candidates: vec![],
+        }
+    }
+
+    // `db.name`, or `name` when `db` is None. The candidates close to `name` are suggested.
+    pub fn no_such_table<'a>(
+        db: Option<&str>,
+        name: &str,
+        candidates: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        Error::missing(Code::NoSuchTable, "no such table: ", db, name, candidates)
+    }
+
+    // `qualifier.name`, or `name` when it is unqualified. The candidates close to `name` are suggested.
+    pub fn no_such_column<'a>(
+        qualifier: Option<&str>,
+        name: &str,
+        candidates: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        Error::missing(
+            Code::NoSuchColumn,
+            "no such column: ",
+            qualifier,
+            name,
+            candidates,
+        )
+    }
+
+    // A column named by an INSERT or UPDATE that `table` doesn't have
+    pub fn no_column_named<'a>(
+        table: &str,
+        name: &str,
+        candidates: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let prefix = format!("table {} has no column named ", table);
+        Error::missing(Code::NoSuchColumn, &prefix, None, name, candidates)
+    }
+
+    // `candidates` are each column `name` could be, qualified by their relation
+    pub fn ambiguous_column(name: &str, candidates: Vec<String>) -> Self {
+        Error::Coded {
+            code: Code::AmbiguousColumn,
+            message: format!(
+                "ambiguous column name: {}. It could be any of {}",
+                name,
+                candidates.join(", ")
+            ),
+            subject: Some(name.to_string()),
+            candidates,
+        }
+    }
+
+    fn missing<'a>(
+        code: Code,
+        prefix: &str,
+        qualifier: Option<&str>,
+        name: &str,
+        candidates: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let subject = match qualifier {
+            Some(qualifier) => format!("{}.{}", qualifier, name),
+            None => name.to_string(),
+        };
+        let similar = util::similar_idents(name, candidates);
+        Error::Coded {
+            code,
+            message: format!(
+                "{}{}{}",
+                prefix,
+                subject,
+                util::did_you_mean(name, similar.iter().copied())
+            ),
+            subject: Some(subject),
+            candidates: similar.into_iter().map(str::to_string).collect(),
+        }
+    }
 }
 
 impl core::fmt::Display for Error {
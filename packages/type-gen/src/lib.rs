@@ -1,36 +1,113 @@
-mod ddl;
-mod error;
-mod queries;
-mod types;
+mod cardinality;
+mod checks;
+mod classify;
+pub mod ddl;
+mod dependencies;
+pub mod diagnostics;
+pub mod error;
+mod nullability;
+pub mod options;
+mod params;
+pub mod queries;
+mod spans;
+mod triggers;
+pub mod types;
 mod util;
+mod walk;
 
-use crate::error::Error;
-use std::collections::HashMap;
+use crate::options::Options;
 
 use types::*;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
-pub fn get_relation_shapes(schema: String) -> Result<JsValue, JsValue> {
-    match ddl::get_relation_shapes(schema) {
+pub fn get_relation_shapes(schema: String, options: JsValue) -> Result<JsValue, JsValue> {
+    let options: Option<Options> = serde_wasm_bindgen::from_value(options)?;
+    match ddl::get_relation_shapes(schema, &options.unwrap_or_default()) {
         Ok(records) => Ok(serde_wasm_bindgen::to_value(&records)?),
-        Err(err) => match err {
-            Error::Other(s) | Error::Parse(s) => Err(JsValue::from(s)),
-        },
+        Err(err) => Err(JsValue::from(err.message())),
     }
 }
 
 #[wasm_bindgen]
-pub fn get_query_result_shapes(query: String, schema: JsValue) -> Result<JsValue, JsError> {
-    let ddl: Vec<NamedRelation> = serde_wasm_bindgen::from_value(schema)?;
-    let record_map: HashMap<_, _> = ddl.into_iter().collect();
+pub fn analyze_schema(schema: String, options: JsValue) -> Result<JsValue, JsError> {
+    let options: Option<Options> = serde_wasm_bindgen::from_value(options)?;
+    match ddl::analyze_schema(schema, &options.unwrap_or_default()) {
+        Ok(shape) => Ok(serde_wasm_bindgen::to_value(&shape)?),
+        Err(e) => Err(JsError::new(e.message())),
+    }
+}
 
-    match queries::get_result_shapes(query, record_map) {
+#[wasm_bindgen]
+pub fn get_query_result_shapes(
+    query: String,
+    schema: JsValue,
+    options: JsValue,
+) -> Result<JsValue, JsError> {
+    let record_map = schema_from_js(schema)?;
+    let options: Option<Options> = serde_wasm_bindgen::from_value(options)?;
+
+    match queries::get_result_shapes(query, record_map, &options.unwrap_or_default()) {
         Ok(shape) => Ok(serde_wasm_bindgen::to_value(&shape)?),
-        Err(Error::Parse(e)) | Err(Error::Other(e)) => Err(JsError::new(&e)),
+        Err(e) => Err(JsError::new(e.message())),
+    }
+}
+
+#[wasm_bindgen]
+pub fn analyze_query(query: String, schema: JsValue, options: JsValue) -> Result<JsValue, JsError> {
+    let record_map = schema_from_js(schema)?;
+    let options: Option<Options> = serde_wasm_bindgen::from_value(options)?;
+
+    match queries::analyze_query(query, record_map, &options.unwrap_or_default()) {
+        Ok(shapes) => Ok(serde_wasm_bindgen::to_value(&shapes)?),
+        Err(e) => Err(JsError::new(e.message())),
     }
 }
 
+#[wasm_bindgen]
+pub fn get_query_dependencies(
+    query: String,
+    schema: JsValue,
+    options: JsValue,
+) -> Result<JsValue, JsError> {
+    let record_map = schema_from_js(schema)?;
+    let options: Option<Options> = serde_wasm_bindgen::from_value(options)?;
+
+    match queries::get_dependencies(query, record_map, &options.unwrap_or_default()) {
+        Ok(dependencies) => Ok(serde_wasm_bindgen::to_value(&dependencies)?),
+        Err(e) => Err(JsError::new(e.message())),
+    }
+}
+
+#[wasm_bindgen]
+pub fn check_query(query: String, schema: JsValue, options: JsValue) -> Result<JsValue, JsError> {
+    let record_map = schema_from_js(schema)?;
+    let options: Option<Options> = serde_wasm_bindgen::from_value(options)?;
+
+    let diagnostics = queries::check_query(query, record_map, &options.unwrap_or_default());
+    Ok(serde_wasm_bindgen::to_value(&diagnostics)?)
+}
+
+// Callers from before `RelationInfo` existed pass `[name, cols]` pairs
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum JsRelation {
+    WithInfo(NamedRelation),
+    WithoutInfo(RelationName, Vec<Col>),
+}
+
+fn schema_from_js(schema: JsValue) -> Result<Schema, JsError> {
+    let ddl: Vec<JsRelation> = serde_wasm_bindgen::from_value(schema)?;
+    Ok(Schema::from_relations(
+        ddl.into_iter()
+            .map(|relation| match relation {
+                JsRelation::WithInfo(relation) => relation,
+                JsRelation::WithoutInfo(name, cols) => (name, cols, RelationInfo::default()),
+            })
+            .collect(),
+    ))
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -0,0 +1,133 @@
+use sqlite3_parser::ast::{Expr, Id, Literal, Name, Operator};
+
+use crate::queries::{self, FromRelation};
+use crate::types::*;
+use crate::util;
+
+// Remove `Null` from the columns `predicate` proves non-null for every row it lets through.
+// Used with WHERE clauses and the ON clauses of inner joins.
+//
+// A row gets through when each of the predicate's top-level AND terms is true. These terms are false or NULL
+// whenever the column they test is NULL:
+// - `col IS NOT NULL` and `col NOTNULL`
+// - `col = x`, `col < x`, etc. and the same with `col` on the right. `IS` is left alone since it accepts NULL.
+// - `col IN (...)` and `col NOT IN (...)` with a non-empty list
+//
+// A column of a relation that an outer join made nullable proves a row of that relation matched.
+// All of its columns go back to the types they had before the join. A LEFT JOIN filtered this way is an INNER JOIN.
+pub fn narrow(relations: &mut [FromRelation], predicate: &Expr) {
+    let mut refs = vec![];
+    non_null_refs(predicate, &mut refs);
+    for col_ref in refs {
+        narrow_ref(relations, col_ref);
+    }
+}
+
+enum ColRef<'a> {
+    Unqualified(&'a str),
+    Qualified(Option<&'a str>, &'a str, &'a str),
+}
+
+fn non_null_refs<'a>(e: &'a Expr, refs: &mut Vec<ColRef<'a>>) {
+    match e {
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => non_null_refs(&exprs[0], refs),
+        Expr::Binary(lhs, Operator::And, rhs) => {
+            non_null_refs(lhs, refs);
+            non_null_refs(rhs, refs);
+        }
+        Expr::NotNull(e) => refs.extend(col_ref(e)),
+        Expr::Binary(lhs, Operator::IsNot, rhs)
+            if matches!(**rhs, Expr::Literal(Literal::Null)) =>
+        {
+            refs.extend(col_ref(lhs))
+        }
+        Expr::Binary(
+            lhs,
+            Operator::Equals
+            | Operator::NotEquals
+            | Operator::Less
+            | Operator::LessEquals
+            | Operator::Greater
+            | Operator::GreaterEquals,
+            rhs,
+        ) => {
+            refs.extend(col_ref(lhs));
+            refs.extend(col_ref(rhs));
+        }
+        Expr::InList {
+            lhs,
+            rhs: Some(rhs),
+            ..
+        } if !rhs.is_empty() => refs.extend(col_ref(lhs)),
+        _ => {}
+    }
+}
+
+fn col_ref(e: &Expr) -> Option<ColRef<'_>> {
+    match e {
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => col_ref(&exprs[0]),
+        Expr::Collate(e, _) => col_ref(e),
+        Expr::Id(Id(name)) | Expr::Name(Name(name)) => Some(ColRef::Unqualified(name)),
+        Expr::Qualified(Name(table), Name(col)) => Some(ColRef::Qualified(None, table, col)),
+        Expr::DoublyQualified(Name(db), Name(table), Name(col)) => {
+            Some(ColRef::Qualified(Some(db), table, col))
+        }
+        _ => None,
+    }
+}
+
+fn narrow_ref(relations: &mut [FromRelation], col_ref: ColRef) {
+    let (index, col_name) = match col_ref {
+        ColRef::Unqualified(name) => {
+            let name = util::unquote_ident(name);
+            let Ok((index, _)) = queries::resolve_unqualified_col(&name, relations) else {
+                return;
+            };
+            // `coalesce(left.col, right.col)` being non-null says nothing about either side on its own
+            let relation = &mut relations[index];
+            if let Some(col) = relation
+                .coalesced
+                .iter_mut()
+                .find(|c| util::ident_eq(&c.0, &name))
+            {
+                remove_null(&mut col.1);
+                return;
+            }
+            (index, name.to_string())
+        }
+        ColRef::Qualified(db, table, col) => {
+            let db = db.map(util::unquote_ident);
+            let table = util::unquote_ident(table);
+            let col = util::unquote_ident(col);
+            let Some(index) = relations
+                .iter()
+                .position(|r| r.is_named(db.as_deref(), &table) && r.col(&col).is_some())
+            else {
+                return;
+            };
+            (index, col.to_string())
+        }
+    };
+
+    let relation = &mut relations[index];
+    if let Some(matched_cols) = relation.matched_cols.take() {
+        relation.cols = matched_cols;
+    }
+    if let Some(col) = relation
+        .cols
+        .iter_mut()
+        .find(|c| util::ident_eq(&c.0, &col_name))
+    {
+        remove_null(&mut col.1);
+    }
+}
+
+fn remove_null(col_type: &mut ColType) {
+    let is_null = |t: &(TypeKind, Option<BuiltinType>, Option<String>)| {
+        matches!(t.1, Some(BuiltinType::Null))
+    };
+    // a column that can only be NULL is left for whoever reads the type to notice
+    if col_type.iter().any(|t| !is_null(t)) {
+        col_type.retain(|t| !is_null(t));
+    }
+}
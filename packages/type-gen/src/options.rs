@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostics::Severity;
+use crate::types::{BuiltinType, Col};
+
+// Knobs callers can turn when asking for shapes.
+// Every field has a default so JS callers only need to pass what they want to change.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Options {
+    // Type columns by the affinity SQLite will store them with rather than by their declared name.
+    // E.g., `VARCHAR(20)` -> String, `DECIMAL` -> Number.
+    // https://www.sqlite.org/datatype3.html#determination_of_column_affinity
+    pub affinity_types: bool,
+    // User overrides for what a declared type means. Checked before any of our built-in rules.
+    // E.g., `[["TIMESTAMP", "Int"], ["VARCHAR(*)", "String"]]`
+    // Names are case-insensitive, `*` matches anything and the first match wins.
+    pub type_mappings: Vec<(String, BuiltinType)>,
+    // Shapes for virtual table modules other than the ones we understand (fts5, rtree, rtree_i32).
+    pub modules: Vec<ModuleShape>,
+    // Results are read as tuples by position, like a driver's `raw()` mode, rather than as objects keyed by name.
+    // Unaliased expressions and duplicate names are fine then. Column names are still returned for display.
+    pub array_mode: bool,
+    // Name unaliased expression columns the way SQLite does, after their text as written. E.g., `count(*)` or `a+1`.
+    // Otherwise an unaliased expression is an error outside of array mode since its name isn't something to rely on.
+    pub sqlite_column_names: bool,
+    // How to report result columns that share a name, like `SELECT a.id, b.id`. Not reported in array mode.
+    // `get_result_shapes` fails on errors since it has nowhere to put diagnostics.
+    pub duplicate_columns: Severity,
+    // Have `get_dependencies` report the columns read and written as well as the relations.
+    pub column_dependencies: bool,
+    // What to do with result columns we can't type, like calls to functions we don't know.
+    pub unresolved_types: UnresolvedTypes,
+}
+
+// `Error` and `Warning` leave the column `Unresolved` and report why. `Any` quietly types it as `Any`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnresolvedTypes {
+    Error,
+    #[default]
+    Warning,
+    Any,
+}
+
+// The columns every virtual table created with module `name` has.
+// E.g., `{ name: "csv", columns: [["c0", [["Builtin", "String", null]]]] }`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ModuleShape {
+    pub name: String,
+    pub columns: Vec<Col>,
+    // Selectable by name but left out of `*`.
+    pub hidden_columns: Vec<Col>,
+}
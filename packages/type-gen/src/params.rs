@@ -0,0 +1,115 @@
+use sqlite3_parser::{
+    ast::{Cmd, ToTokens, TokenStream},
+    dialect::TokenType,
+};
+
+use crate::types::*;
+
+struct Tokens {
+    pub tokens: Vec<(TokenType, Option<String>)>,
+}
+
+impl TokenStream for Tokens {
+    type Error = String;
+
+    fn append(&mut self, ty: TokenType, value: Option<&str>) -> Result<(), Self::Error> {
+        self.tokens.push((ty, value.map(|v| v.to_string())));
+        Ok(())
+    }
+}
+
+// The parameters of a statement in bind order.
+// Indices follow sqlite3_bind_parameter_index: `?NNN` takes NNN, a repeated name reuses its index
+// and everything else takes one more than the largest index so far.
+// https://www.sqlite.org/lang_expr.html#parameters
+//
+// Only the right hand side of MATCH is typed for now. All other parameters are `Unspecified`.
+pub fn params(cmd: &Cmd) -> Vec<Param> {
+    let mut tokens = Tokens { tokens: vec![] };
+    cmd.to_tokens(&mut tokens).unwrap(); // Tokens always returns Ok
+
+    let mut ret: Vec<Param> = vec![];
+    let mut max_index = 0;
+    let mut prev: Option<&(TokenType, Option<String>)> = None;
+    for token in &tokens.tokens {
+        if let (TokenType::TK_VARIABLE, Some(var)) = token {
+            let existing = ret
+                .iter()
+                .position(|p| var != "?" && p.name.as_deref() == Some(var.as_str()));
+            let index = match (existing, var.strip_prefix('?')) {
+                (Some(i), _) => ret[i].index,
+                (None, Some(n)) if !n.is_empty() => n.parse().unwrap_or(max_index + 1),
+                _ => max_index + 1,
+            };
+            max_index = max_index.max(index);
+
+            let col_type = match prev {
+                Some((TokenType::TK_LIKE_KW, Some(op))) if op.eq_ignore_ascii_case("match") => {
+                    builtin_type(BuiltinType::String)
+                }
+                _ => builtin_type(BuiltinType::Unspecified),
+            };
+            match ret.iter_mut().find(|p| p.index == index) {
+                // the same parameter used in many places. Keep the most specific type we found for it.
+                Some(p) => {
+                    if p.col_type == builtin_type(BuiltinType::Unspecified) {
+                        p.col_type = col_type;
+                    }
+                }
+                None => ret.push(Param {
+                    index,
+                    name: if var == "?" {
+                        None
+                    } else {
+                        Some(var.to_string())
+                    },
+                    col_type,
+                }),
+            }
+        }
+        prev = Some(token);
+    }
+
+    ret.sort_by_key(|p| p.index);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fallible_iterator::FallibleIterator;
+    use sqlite3_parser::lexer::sql::Parser;
+
+    fn params_of(sql: &str) -> Vec<(usize, Option<String>, ColType)> {
+        let mut parser = Parser::new(sql.as_bytes());
+        params(&parser.next().unwrap().unwrap())
+            .into_iter()
+            .map(|p| (p.index, p.name, p.col_type))
+            .collect()
+    }
+
+    #[test]
+    fn numbering() {
+        let unspecified = builtin_type(BuiltinType::Unspecified);
+        assert_eq!(
+            params_of("SELECT * FROM foo WHERE a = ? AND b = :b AND c = ?5 AND d = ? AND e = :b"),
+            vec![
+                (1, None, unspecified.clone()),
+                (2, Some(":b".to_string()), unspecified.clone()),
+                (5, Some("?5".to_string()), unspecified.clone()),
+                (6, None, unspecified),
+            ]
+        );
+    }
+
+    #[test]
+    fn match_rhs_is_a_string() {
+        assert_eq!(
+            params_of("SELECT * FROM docs WHERE a = ? AND docs MATCH ?"),
+            vec![
+                (1, None, builtin_type(BuiltinType::Unspecified)),
+                (2, None, builtin_type(BuiltinType::String)),
+            ]
+        );
+    }
+}
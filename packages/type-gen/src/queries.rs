@@ -1,16 +1,26 @@
+use crate::cardinality;
+use crate::classify;
+use crate::dependencies;
+use crate::diagnostics::{Code, Diagnostic, Severity};
+use crate::nullability;
+use crate::options::{Options, UnresolvedTypes};
+use crate::params;
+use crate::spans;
 use crate::util;
+use crate::walk::walk_expr;
 use crate::{error::Error, types::*};
 use fallible_iterator::FallibleIterator;
 use sqlite3_parser::{
     ast::{
-        As, Cmd, Expr, FromClause, Id, JoinOperator, JoinType, JoinedSelectTable, Literal, Name,
-        OneSelect, Operator, QualifiedName, ResultColumn, Select, SelectTable, Stmt, ToTokens,
-        TokenStream, UnaryOperator,
+        As, Cmd, CompoundSelect, Expr, FromClause, GroupBy, Id, JoinConstraint, JoinOperator,
+        JoinType, JoinedSelectTable, Literal, Name, OneSelect, Operator, QualifiedName,
+        ResultColumn, Select, SelectTable, Stmt, ToTokens, TokenStream, UnaryOperator,
     },
     dialect::TokenType,
     lexer::sql::Parser,
 };
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::RefCell;
 
 // struct Selected {
 //     pub qualifier: Option<String>,
@@ -20,52 +30,363 @@ use std::collections::HashMap;
 //     pub c_num: u32,
 // }
 
+// Renders an expression back to SQL. Keywords and punctuation come from their token types.
 struct TokenCollector {
-    pub parts: Vec<String>,
+    pub parts: Vec<(TokenType, String)>,
 }
 
-impl TokenCollector {
-    pub fn to_string(&self) -> String {
-        // spaces or no?
-        self.parts.join(" ")
+impl std::fmt::Display for TokenCollector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut prev: Option<TokenType> = None;
+        for (ty, part) in &self.parts {
+            let spaced = match (prev, ty) {
+                (None, _) => false,
+                (Some(TokenType::TK_LP | TokenType::TK_DOT), _) => false,
+                (_, TokenType::TK_RP | TokenType::TK_COMMA | TokenType::TK_DOT) => false,
+                // a function call
+                (Some(TokenType::TK_ID), TokenType::TK_LP) => false,
+                _ => true,
+            };
+            if spaced {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", part)?;
+            prev = Some(*ty);
+        }
+        Ok(())
     }
 }
 
 impl TokenStream for TokenCollector {
     type Error = String;
 
-    fn append(&mut self, _ty: TokenType, value: Option<&str>) -> Result<(), Self::Error> {
-        if let Some(s) = value {
-            self.parts.push(s.to_string());
+    fn append(&mut self, ty: TokenType, value: Option<&str>) -> Result<(), Self::Error> {
+        if let Some(s) = value.or(ty.as_str()) {
+            self.parts.push((ty, s.to_string()));
         }
 
         Ok(())
     }
 }
 
+// Everything the analysis needs that stays fixed as we descend into a statement.
+#[derive(Clone, Copy)]
+pub(crate) struct Context<'a> {
+    pub schema: &'a Schema,
+    pub options: &'a Options,
+    // Common table expressions in scope, innermost first. They shadow relations of the schema.
+    pub ctes: &'a [FromRelation],
+    // The source text of the result columns of the statement's outermost select, when SQLite's names were asked for.
+    // Nested selects don't see these.
+    pub column_texts: &'a [String],
+    // Where problems that don't stop the analysis go. Selects in FROM clauses and common table expressions
+    // share the statement's. None for other nested selects, which fail on their first problem.
+    pub diagnostics: Option<&'a RefCell<Vec<Diagnostic>>>,
+    // Whether this is the statement's outermost select, the one whose columns are the result
+    pub outermost: bool,
+}
+
 pub fn get_result_shapes(
     query: String,
-    // TODO: we need to qualify relation names with `main`
-    schema: HashMap<RelationName, Vec<Col>>,
+    schema: Schema,
+    options: &Options,
 ) -> Result<Vec<Relation>, Error> {
+    let shapes = analyze_query(query, schema, options)?;
+    // there's nowhere to put diagnostics in plain relations so the ones that were asked to be errors become one
+    if let Some(diagnostic) = shapes
+        .iter()
+        .flat_map(|shape| &shape.diagnostics)
+        .find(|d| d.severity == Severity::Error)
+    {
+        return Err(Error::Other(diagnostic.message.clone()));
+    }
+    Ok(shapes
+        .into_iter()
+        .filter(|shape| {
+            matches!(
+                shape.statement.kind,
+                StatementKind::Select | StatementKind::Explain
+            )
+        })
+        .map(|shape| shape.relation)
+        .collect())
+}
+
+// Like `get_result_shapes` but each shape comes with extra information for runtimes and tooling.
+// There is a shape for every statement, not just those that return rows.
+pub fn analyze_query(
+    query: String,
+    schema: Schema,
+    options: &Options,
+) -> Result<Vec<ResultShape>, Error> {
     let mut parser = Parser::new(query.as_bytes());
     let mut ret = vec![];
+    let mut stmt_start = 0;
 
     while let Some(cmd) = parser.next()? {
-        if let Some(relation) = get_result_shape(cmd, &schema)? {
-            ret.push(relation)
+        let stmt_end = spans::offset_of(&query, parser.line(), parser.column());
+        ret.push(analyze_statement(
+            cmd, &query, stmt_start, stmt_end, &schema, options,
+        ));
+        stmt_start = stmt_end;
+    }
+
+    Ok(ret)
+}
+
+// Every problem found in `query`, each pointing at where it is.
+// A syntax error stops it since what follows can't be told apart.
+pub fn check_query(query: String, schema: Schema, options: &Options) -> Vec<Diagnostic> {
+    let mut parser = Parser::new(query.as_bytes());
+    let mut ret = vec![];
+    let mut stmt_start = 0;
+
+    loop {
+        let cmd = match parser.next() {
+            Ok(Some(cmd)) => cmd,
+            Ok(None) => break,
+            Err(e) => {
+                ret.push(Diagnostic::syntax(&e, &query));
+                break;
+            }
+        };
+        let stmt_end = spans::offset_of(&query, parser.line(), parser.column());
+        ret.extend(
+            analyze_statement(cmd, &query, stmt_start, stmt_end, &schema, options).diagnostics,
+        );
+        stmt_start = stmt_end;
+    }
+
+    ret
+}
+
+// The statement of `query` between the byte offsets `stmt_start` and `stmt_end`.
+fn analyze_statement(
+    cmd: Cmd,
+    query: &str,
+    stmt_start: usize,
+    stmt_end: usize,
+    schema: &Schema,
+    options: &Options,
+) -> ResultShape {
+    let column_texts = if options.array_mode || options.sqlite_column_names {
+        spans::result_column_texts(&query[stmt_start..stmt_end])
+    } else {
+        vec![]
+    };
+    let diagnostics = RefCell::new(vec![]);
+    let ctx = Context {
+        schema,
+        options,
+        ctes: &[],
+        column_texts: &column_texts,
+        diagnostics: Some(&diagnostics),
+        outermost: true,
+    };
+    let params = params::params(&cmd);
+    let statement = classify::classify(&cmd);
+    let cardinality = if statement.returns_rows {
+        // problems are reported once, by typing the result below
+        cardinality::cardinality(
+            &cmd,
+            &Context {
+                diagnostics: None,
+                ..ctx
+            },
+        )
+    } else {
+        Cardinality::Zero
+    };
+    // every statement gets a shape so callers know how to run it, even those without rows or that we can't type
+    let (mut relation, origins) = match get_result_shape(cmd, &ctx) {
+        Ok(shape) => shape.unwrap_or_default(),
+        Err(error) => {
+            diagnostics.borrow_mut().push(Diagnostic::from(error));
+            Default::default()
+        }
+    };
+    unresolved_columns(&mut relation, &mut diagnostics.borrow_mut(), options);
+    ResultShape {
+        converters: converters(&relation, &origins, schema),
+        relation,
+        params,
+        cardinality,
+        diagnostics: diagnostics
+            .into_inner()
+            .into_iter()
+            .map(|d| d.locate(query, stmt_start, stmt_end))
+            .collect(),
+        origins,
+        statement,
+        span: spans::statement_span(query, stmt_start, stmt_end),
+    }
+}
+
+// Result columns we couldn't type are reported or typed as `Any`, as the options ask.
+// Those left unresolved by an error were already reported.
+fn unresolved_columns(
+    relation: &mut Relation,
+    diagnostics: &mut Vec<Diagnostic>,
+    options: &Options,
+) {
+    for (name, col_type) in relation.1.iter_mut() {
+        let severity = match options.unresolved_types {
+            UnresolvedTypes::Any => {
+                for part in col_type.iter_mut() {
+                    if part.0 == TypeKind::Unresolved {
+                        *part = (TypeKind::Builtin, Some(BuiltinType::Any), None);
+                    }
+                }
+                continue;
+            }
+            UnresolvedTypes::Error => Severity::Error,
+            UnresolvedTypes::Warning => Severity::Warning,
+        };
+        let reasons = col_type
+            .iter()
+            .filter(|part| part.0 == TypeKind::Unresolved)
+            .filter_map(|part| part.2.as_deref())
+            .filter(|reason| !diagnostics.iter().any(|d| d.message == *reason))
+            .collect::<Vec<_>>();
+        if let Some(reason) = reasons.first() {
+            diagnostics.push(
+                Diagnostic::new(
+                    Code::Unresolved,
+                    severity,
+                    format!("can't type column {}: {}", name, reason),
+                )
+                .about(name.to_string()),
+            );
         }
     }
+}
 
+// What each statement of `query` reads and writes. One entry per statement.
+pub fn get_dependencies(
+    query: String,
+    schema: Schema,
+    options: &Options,
+) -> Result<Vec<Dependencies>, Error> {
+    let mut parser = Parser::new(query.as_bytes());
+    let ctx = Context {
+        schema: &schema,
+        options,
+        ctes: &[],
+        column_texts: &[],
+        diagnostics: None,
+        outermost: false,
+    };
+    let mut ret = vec![];
+    while let Some(cmd) = parser.next()? {
+        ret.push(dependencies::dependencies(&cmd, &ctx));
+    }
     Ok(ret)
 }
 
-fn get_result_shape(
-    node: Cmd,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Result<Option<Relation>, Error> {
+// A relation brought into scope by a FROM clause, along with what the schema knows about it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FromRelation {
+    // The database a table was found in. Aliases and subqueries don't belong to a database.
+    pub db: Option<String>,
+    // The table name or alias the relation can be referred to by
+    pub name: Option<String>,
+    pub cols: Vec<Col>,
+    pub info: RelationInfo,
+    // Columns a USING or NATURAL join merged into a relation to the left of this one.
+    // An unqualified reference to them is not ambiguous and refers to the left relation's column.
+    pub merged: Vec<ColName>,
+    // The types of columns a RIGHT or FULL join merged into this relation's column.
+    // These are what unqualified references and `*` see. Qualified references get the column's own type.
+    pub coalesced: Vec<Col>,
+    // The columns as they were before an outer join made them nullable
+    pub matched_cols: Option<Vec<Col>>,
+    // How many selects out the relation comes from. 0 for the select's own FROM clause.
+    // Unqualified names resolve against the innermost scope that has them.
+    pub depth: usize,
+    // The table column each of `cols` is read from, in the same order. Missing entries are computed columns.
+    pub origins: Vec<Option<ColumnOrigin>>,
+}
+
+impl FromRelation {
+    // Whether `db.name.col` or `name.col` would refer to this relation
+    pub fn is_named(&self, db: Option<&str>, name: &str) -> bool {
+        self.name
+            .as_deref()
+            .is_some_and(|n| util::ident_eq(n, name))
+            && db.map_or(true, |db| {
+                self.db.as_deref().is_some_and(|d| util::ident_eq(d, db))
+            })
+    }
+
+    // The column `name` refers to, spelled as declared
+    pub fn col(&self, name: &str) -> Option<&Col> {
+        self.cols.iter().find(|c| util::ident_eq(&c.0, name))
+    }
+
+    // The columns `*` expands to
+    fn star_cols(&self) -> Vec<Col> {
+        self.cols
+            .iter()
+            .filter(|c| {
+                !self
+                    .info
+                    .hidden_columns
+                    .iter()
+                    .any(|h| util::ident_eq(h, &c.0))
+            })
+            .cloned()
+            .collect()
+    }
+
+    // The column an unqualified `name` refers to when it resolves to this relation
+    pub fn unqualified_col(&self, name: &str) -> Option<&Col> {
+        self.coalesced
+            .iter()
+            .find(|c| util::ident_eq(&c.0, name))
+            .or_else(|| self.col(name))
+    }
+
+    // The table column `name` is read from. None when it's computed.
+    // An unqualified reference to a column a RIGHT or FULL join coalesced could come from either side.
+    pub fn origin(&self, name: &str, unqualified: bool) -> Option<ColumnOrigin> {
+        if unqualified && self.coalesced.iter().any(|c| util::ident_eq(&c.0, name)) {
+            return None;
+        }
+        let i = self.cols.iter().position(|c| util::ident_eq(&c.0, name))?;
+        self.origins.get(i).cloned().flatten()
+    }
+
+    // The columns a bare `*` expands to. Merged columns were already expanded with the relation they merged into.
+    fn unqualified_star_cols(&self) -> Vec<Col> {
+        self.star_cols()
+            .into_iter()
+            .filter(|c| !self.merged.iter().any(|m| util::ident_eq(m, &c.0)))
+            .map(|c| self.unqualified_col(&c.0).cloned().unwrap_or(c))
+            .collect()
+    }
+}
+
+impl From<Relation> for FromRelation {
+    fn from(relation: Relation) -> Self {
+        FromRelation {
+            name: relation.0,
+            cols: relation.1,
+            ..FromRelation::default()
+        }
+    }
+}
+
+// The table column each column of a relation is read from, in order. None for computed columns.
+type Origins = Vec<Option<ColumnOrigin>>;
+
+// The relation a statement returns along with the origin of each of its columns
+fn get_result_shape(node: Cmd, ctx: &Context) -> Result<Option<(Relation, Origins)>, Error> {
+    let computed = |relation: Relation| {
+        let origins = vec![None; relation.1.len()];
+        Some((relation, origins))
+    };
     match node {
-        Cmd::Explain(_) => Ok(Some((
+        Cmd::Explain(_) => Ok(computed((
             None,
             vec![
                 (String::from("addr"), builtin_type(BuiltinType::Int)),
@@ -78,186 +399,648 @@ fn get_result_shape(
                 (String::from("comment"), builtin_type(BuiltinType::String)),
             ],
         ))),
-        Cmd::ExplainQueryPlan(_) => Ok(Some((
+        Cmd::ExplainQueryPlan(_) => Ok(computed((
             None,
             vec![(
                 String::from("QUERY PLAN"),
                 builtin_type(BuiltinType::String),
             )],
         ))),
-        Cmd::Stmt(Stmt::Select(select)) => Ok(Some(select_to_relation(&select, &vec![], schema)?)),
+        Cmd::Stmt(Stmt::Select(select)) => {
+            Ok(Some(select_to_relation_with_origins(&select, &[], ctx)?))
+        }
         // TODO: update & returning, insert & returning, delete & returning
         Cmd::Stmt(_) => Ok(None),
     }
 }
 
-fn select_to_relation(
+pub(crate) fn select_to_relation(
     select: &Select,
-    outer_from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
+    outer_from_relations: &[FromRelation],
+    ctx: &Context,
 ) -> Result<Relation, Error> {
-    let selection_set = selection_set(&select)?;
+    Ok(select_to_relation_with_origins(select, outer_from_relations, ctx)?.0)
+}
+
+// Like `select_to_relation` but also returns the table column each result column is read from
+fn select_to_relation_with_origins(
+    select: &Select,
+    outer_from_relations: &[FromRelation],
+    ctx: &Context,
+) -> Result<(Relation, Origins), Error> {
+    let mut column_texts = ctx.column_texts;
+    let diagnostics = ctx.diagnostics;
+    let outermost = ctx.outermost;
+    // the relations a select reads from are typed as far as they can be, like the select itself
+    let scoped = Context {
+        column_texts: &[],
+        outermost: false,
+        ..*ctx
+    };
+    let ctes = with_relations(select, outer_from_relations, &scoped)?;
+    let scoped = Context {
+        ctes: &ctes,
+        ..scoped
+    };
+    let ctx = &Context {
+        diagnostics: None,
+        ..scoped
+    };
+    let selection_set = selection_set(select)?;
+    if column_texts.len() != selection_set.len() {
+        column_texts = &[];
+    }
     // selection set could contain stars
     // if this is the case we pull all columns from all relations in-order and name them.. whatever they were named there.
-    let mut from_relations = from_relations(&select, outer_from_relations, schema)?;
-    from_relations.extend(outer_from_relations.clone());
-    // TODO: can withs be nested? I don't think so but the current AST allows it.
-    let with_relations = with_relations(&select);
-
-    let mut schema = schema.clone();
-    schema.extend(with_relations);
+    let from_relations = from_relations(&select.body.select, outer_from_relations, &scoped)?;
+    let mut from_relations = with_outer(from_relations, outer_from_relations);
+    if let OneSelect::Select {
+        where_clause: Some(where_clause),
+        ..
+    } = &select.body.select
+    {
+        nullability::narrow(&mut from_relations, where_clause);
+    }
 
     // now craft the result shape by marrying the selection_set with the from_shape.
     // for naked expression selects, determine type of the expression
-    // with_relations act as additional schemas atop our base schema
+    // with_relations act as additional relations atop our base schema
 
     // if items in the selection set are expressions, convert the expression to a type and pair it with a column name
 
     // selection set is picking items out of from, with and schema.
     // then returning a new relation. This relation may be unnamed.
 
-    // TODO: oof. Need a better way to propagate errors from lambdas.
-    let mut err: Result<_, Error> = Ok(());
-    let cols = selection_set
+    // A column we can't type doesn't stop the others from being typed. It's left unresolved and,
+    // when there are diagnostics to report it to, reported along with the shape. Otherwise the select fails on the first.
+    let mut errors = vec![];
+    let (cols, sources): (Vec<Col>, Vec<ColSource>) = selection_set
         .iter()
-        .flat_map(|result_column| -> Vec<Col> {
+        .enumerate()
+        .flat_map(|(i, result_column)| -> Vec<(Col, ColSource)> {
             match result_column {
-                ResultColumn::Expr(e, Some(as_)) => {
-                    match resolve_selection_set_expr_type(e, &from_relations, &schema) {
-                        Ok(t) => vec![(extract_alias(as_).to_string(), t)],
-                        Err(e) => {
-                            err = Err(e);
-                            vec![]
+                ResultColumn::Expr(e, as_) => {
+                    let alias = as_.as_ref().map(|as_| extract_alias(as_).to_string());
+                    let (col_name, t) = match resolve_selection_set_expr(e, &from_relations, ctx) {
+                        Ok(resolved) => resolved,
+                        Err(error) => {
+                            let t = unresolved_type(error.message().to_string());
+                            errors.push(error);
+                            (None, t)
                         }
-                    }
-                }
-                ResultColumn::Expr(e, None) => {
-                    match resolve_selection_set_expr_type(e, &from_relations, &schema) {
-                        Ok(t) => match expression_to_col_name(e) {
-                            Ok(col_name) => vec![(col_name, t)],
-                            Err(e) => {
-                                err = Err(e);
-                                vec![]
-                            }
-                        },
-                        Err(e) => {
-                            err = Err(e);
-                            vec![]
+                    };
+                    match (alias, col_name) {
+                        (Some(alias), _) => vec![((alias, t), col_source(e, &from_relations))],
+                        // a column keeps the name it was declared with regardless of how it was referenced
+                        (None, Some(col_name)) => {
+                            vec![((col_name, t), col_source(e, &from_relations))]
+                        }
+                        (None, None) => {
+                            let col_name = expression_to_col_name(e, column_texts.get(i), ctx)
+                                .unwrap_or_else(|error| {
+                                    errors.push(error);
+                                    column_texts
+                                        .get(i)
+                                        .cloned()
+                                        .unwrap_or_else(|| expression_text(e))
+                                });
+                            vec![((col_name, t), ColSource::default())]
                         }
                     }
                 }
                 ResultColumn::Star => {
-                    // grab everything exposed by `from_relations`
+                    // grab everything exposed by this select's own `from_relations`
                     from_relations
                         .iter()
-                        .flat_map(|relation| -> Vec<Col> { relation.1.to_vec() })
+                        .filter(|relation| relation.depth == 0)
+                        .flat_map(|relation| {
+                            relation.unqualified_star_cols().into_iter().map(|col| {
+                                let source = ColSource {
+                                    relation: relation.name.clone(),
+                                    origin: relation.origin(&col.0, true),
+                                };
+                                (col, source)
+                            })
+                        })
                         .collect()
                 }
                 ResultColumn::TableStar(Name(table_name)) => {
                     // grab everything exposed by the chosen table
                     // the chosen table name must exist in from_relations
                     for relation in &from_relations {
-                        if let Some(name) = &relation.0 {
-                            if &format!("main.{}", util::unquote_ident(table_name)) == name {
-                                return relation.1.to_vec();
-                            }
+                        if relation.is_named(None, &util::unquote_ident(table_name)) {
+                            return relation
+                                .star_cols()
+                                .into_iter()
+                                .map(|col| {
+                                    let source = ColSource {
+                                        relation: relation.name.clone(),
+                                        origin: relation.origin(&col.0, false),
+                                    };
+                                    (col, source)
+                                })
+                                .collect();
                         }
                     }
-                    err = Err(Error::Other(format!(
-                        "Unable to find relation with name {}",
-                        table_name
-                    )));
+                    // there's no telling what columns an unknown table has
+                    let candidates = from_relations.iter().filter_map(|r| r.name.as_deref());
+                    errors.push(Error::no_such_table(None, table_name, candidates));
                     vec![]
                 }
             }
         })
         .collect();
 
-    if let Err(err) = err {
-        Err(err)
-    } else {
-        Ok((None, cols))
+    // the result columns are checked again along with the other clauses
+    let compound_errors = check_compounds(select, outer_from_relations, ctx);
+    for error in check_clauses(select, &selection_set, &from_relations, &cols, ctx)
+        .into_iter()
+        .chain(compound_errors)
+    {
+        if !errors.contains(&error) {
+            errors.push(error);
+        }
+    }
+    match diagnostics {
+        Some(diagnostics) => {
+            let mut diagnostics = diagnostics.borrow_mut();
+            diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+            // rows read by position don't care about names
+            if outermost && !ctx.options.array_mode {
+                diagnostics.extend(duplicate_columns(&cols, &sources, ctx.options));
+            }
+        }
+        None => {
+            if let Some(error) = errors.into_iter().next() {
+                return Err(error);
+            }
+        }
+    }
+    let origins = sources.into_iter().map(|source| source.origin).collect();
+    Ok(((None, cols), origins))
+}
+
+// Where a result column is read from. Both are None when it's computed.
+#[derive(Default)]
+struct ColSource {
+    // The name of the relation it was selected from
+    relation: Option<String>,
+    origin: Option<ColumnOrigin>,
+}
+
+fn col_source(e: &Expr, from_relations: &[FromRelation]) -> ColSource {
+    let (relation, col_name, unqualified) = match e {
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => {
+            return col_source(&exprs[0], from_relations)
+        }
+        Expr::Id(Id(name)) | Expr::Name(Name(name)) => {
+            let name = util::unquote_ident(name);
+            let Ok((index, _)) = resolve_unqualified_col(&name, from_relations) else {
+                return ColSource::default();
+            };
+            (&from_relations[index], name, true)
+        }
+        Expr::Qualified(Name(table), Name(name)) => {
+            let table = util::unquote_ident(table);
+            let name = util::unquote_ident(name);
+            let Some(relation) = from_relations
+                .iter()
+                .find(|r| r.is_named(None, &table) && r.col(&name).is_some())
+            else {
+                return ColSource::default();
+            };
+            (relation, name, false)
+        }
+        Expr::DoublyQualified(Name(db), Name(table), Name(name)) => {
+            let db = util::unquote_ident(db);
+            let table = util::unquote_ident(table);
+            let name = util::unquote_ident(name);
+            let Some(relation) = from_relations
+                .iter()
+                .find(|r| r.is_named(Some(&db), &table) && r.col(&name).is_some())
+            else {
+                return ColSource::default();
+            };
+            (relation, name, false)
+        }
+        _ => return ColSource::default(),
+    };
+    ColSource {
+        relation: relation.name.clone(),
+        origin: relation.origin(&col_name, unqualified),
+    }
+}
+
+// Result columns that share a name. Read as an object, the last of them silently replaces the others.
+// Names are compared exactly since that's how object keys compare.
+fn duplicate_columns(cols: &[Col], sources: &[ColSource], options: &Options) -> Vec<Diagnostic> {
+    let mut ret = vec![];
+    for (i, (name, _)) in cols.iter().enumerate() {
+        if cols[..i].iter().any(|(other, _)| other == name) {
+            continue;
+        }
+        let clashing = (i..cols.len())
+            .filter(|&j| cols[j].0 == *name)
+            .collect::<Vec<_>>();
+        if clashing.len() < 2 {
+            continue;
+        }
+        let from = clashing
+            .iter()
+            .map(|&j| match &sources[j].relation {
+                Some(relation) => relation.clone(),
+                None => "an expression".to_string(),
+            })
+            .collect::<Vec<_>>();
+        // `a_id, b_id` when each clashing column comes from a different relation, `id_1, id_2` otherwise
+        let distinct_sources = clashing.iter().all(|&j| {
+            sources[j].relation.is_some()
+                && clashing
+                    .iter()
+                    .filter(|&&k| sources[k].relation == sources[j].relation)
+                    .count()
+                    == 1
+        });
+        let aliases = clashing
+            .iter()
+            .enumerate()
+            .map(|(n, &j)| match &sources[j].relation {
+                Some(relation) if distinct_sources => {
+                    format!("{}_{}", relation.replace('.', "_"), name)
+                }
+                _ => format!("{}_{}", name, n + 1),
+            })
+            .collect::<Vec<_>>();
+        ret.push(
+            Diagnostic::new(
+                Code::DuplicateColumn,
+                options.duplicate_columns,
+                format!(
+                    "duplicate column name {} from {}. Only the last is kept when rows are read as objects. Alias them (AS) apart, e.g. {}",
+                    name,
+                    from.join(", "),
+                    aliases.join(", ")
+                ),
+            )
+            .about(name.to_string()),
+        );
+    }
+    ret
+}
+
+// The relations a select reads from followed by those of the selects it's nested in
+fn with_outer(
+    mut from_relations: Vec<FromRelation>,
+    outer_from_relations: &[FromRelation],
+) -> Vec<FromRelation> {
+    from_relations.extend(outer_from_relations.iter().map(|r| FromRelation {
+        depth: r.depth + 1,
+        ..r.clone()
+    }));
+    from_relations
+}
+
+// Names anywhere in the select have to resolve, not just those that shape the result. Returns every one that doesn't.
+// WHERE, GROUP BY, HAVING and ORDER BY can also refer to result columns by name and GROUP BY and ORDER BY by position.
+// https://www.sqlite.org/lang_select.html#the_order_by_clause
+fn check_clauses(
+    select: &Select,
+    selection_set: &[ResultColumn],
+    from_relations: &[FromRelation],
+    cols: &[Col],
+    ctx: &Context,
+) -> Vec<Error> {
+    let result_names = cols.iter().map(|c| c.0.as_str()).collect::<Vec<_>>();
+    let mut errors = check_one_select(
+        &select.body.select,
+        selection_set,
+        from_relations,
+        &result_names,
+        ctx,
+    );
+    for (i, term) in select.order_by.iter().flatten().enumerate() {
+        errors.extend(
+            check_term(
+                "ORDER BY",
+                i,
+                &term.expr,
+                from_relations,
+                &result_names,
+                ctx,
+            )
+            .err(),
+        );
+    }
+    errors
+}
+
+// The selects after UNION, INTERSECT or EXCEPT don't shape the result but are checked all the same.
+// Each one reads from its own FROM clause.
+fn check_compounds(
+    select: &Select,
+    outer_from_relations: &[FromRelation],
+    ctx: &Context,
+) -> Vec<Error> {
+    let mut errors = vec![];
+    for CompoundSelect { select: one, .. } in select.body.compounds.iter().flatten() {
+        let from_relations = match from_relations(one, outer_from_relations, ctx) {
+            Ok(from_relations) => with_outer(from_relations, outer_from_relations),
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        };
+        let OneSelect::Select { columns, .. } = one else {
+            continue;
+        };
+        let result_names = columns
+            .iter()
+            .map(|column| match column {
+                ResultColumn::Expr(_, Some(as_)) => extract_alias(as_),
+                _ => Cow::Borrowed(""),
+            })
+            .collect::<Vec<_>>();
+        let result_names = result_names.iter().map(|n| n.as_ref()).collect::<Vec<_>>();
+        errors.extend(check_one_select(
+            one,
+            columns,
+            &from_relations,
+            &result_names,
+            ctx,
+        ));
+    }
+    errors
+}
+
+// The result columns, WHERE, GROUP BY and HAVING of a single select
+fn check_one_select(
+    select: &OneSelect,
+    selection_set: &[ResultColumn],
+    from_relations: &[FromRelation],
+    result_names: &[&str],
+    ctx: &Context,
+) -> Vec<Error> {
+    let mut errors = vec![];
+    for result_column in selection_set {
+        if let ResultColumn::Expr(e, _) = result_column {
+            errors.extend(check_names(e, from_relations, &[], ctx).err());
+        }
+    }
+    if let OneSelect::Select {
+        where_clause,
+        group_by,
+        ..
+    } = select
+    {
+        if let Some(where_clause) = where_clause {
+            errors.extend(check_names(where_clause, from_relations, result_names, ctx).err());
+        }
+        if let Some(GroupBy { exprs, having }) = group_by {
+            for (i, e) in exprs.iter().enumerate() {
+                errors
+                    .extend(check_term("GROUP BY", i, e, from_relations, result_names, ctx).err());
+            }
+            if let Some(having) = having {
+                errors.extend(check_names(having, from_relations, result_names, ctx).err());
+            }
+        }
+    }
+    errors
+}
+
+// A GROUP BY or ORDER BY term. An integer picks a result column by position.
+fn check_term(
+    clause: &str,
+    i: usize,
+    e: &Expr,
+    from_relations: &[FromRelation],
+    result_names: &[&str],
+    ctx: &Context,
+) -> Result<(), Error> {
+    if let Expr::Literal(Literal::Numeric(n)) = e {
+        if let Ok(n) = n.parse::<usize>() {
+            if n < 1 || n > result_names.len() {
+                return Err(Error::coded(
+                    Code::OutOfRange,
+                    format!(
+                        "{} {} term out of range - should be between 1 and {}",
+                        ordinal(i + 1),
+                        clause,
+                        result_names.len()
+                    ),
+                ));
+            }
+            return Ok(());
+        }
+    }
+    check_names(e, from_relations, result_names, ctx)
+}
+
+// 1st, 2nd, 3rd, 4th, ..., 11th, ..., 21st
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+// Every column `e` refers to must resolve against `from_relations`, or be one of `result_names`.
+// Subqueries are checked with `from_relations` as their outer scope.
+fn check_names(
+    e: &Expr,
+    from_relations: &[FromRelation],
+    result_names: &[&str],
+    ctx: &Context,
+) -> Result<(), Error> {
+    let mut refs = vec![];
+    let mut subqueries = vec![];
+    let mut fts5_calls = vec![];
+    walk_expr(e, &mut |e| match e {
+        Expr::Id(_) | Expr::Name(_) | Expr::Qualified(..) | Expr::DoublyQualified(..) => {
+            refs.push(e)
+        }
+        Expr::FunctionCall {
+            name: Id(n), args, ..
+        } if is_fts5_auxiliary_fn(n) => fts5_calls.push((n, args)),
+        Expr::Subquery(select) | Expr::Exists(select) | Expr::InSelect { rhs: select, .. } => {
+            subqueries.push(select.as_ref())
+        }
+        _ => {}
+    });
+    let has_rowid = from_relations.iter().any(|r| !r.info.without_rowid);
+    for e in refs {
+        let Err(err) = resolve_selection_set_expr(e, from_relations, ctx) else {
+            continue;
+        };
+        let tolerated = match e {
+            Expr::Id(Id(name)) | Expr::Name(Name(name)) => {
+                // a double quoted name that isn't a column is a string literal
+                name.starts_with('"')
+                    || util::ident_eq(name, "true")
+                    || util::ident_eq(name, "false")
+                    || (has_rowid && is_rowid_alias(name))
+                    // a virtual table's name or alias refers to its table-named hidden column. E.g., `d MATCH ?`
+                    || from_relations.iter().any(|r| {
+                        r.info.module.is_some() && r.is_named(None, &util::unquote_ident(name))
+                    })
+                    || result_names
+                        .iter()
+                        .any(|n| util::ident_eq(n, &util::unquote_ident(name)))
+            }
+            Expr::Qualified(_, Name(col)) | Expr::DoublyQualified(_, _, Name(col)) => {
+                has_rowid && is_rowid_alias(&util::unquote_ident(col))
+            }
+            _ => false,
+        };
+        if !tolerated {
+            return Err(err);
+        }
+    }
+    for (fn_name, args) in fts5_calls {
+        check_fts5_table_arg(fn_name, args, from_relations)?;
     }
+    for select in subqueries {
+        select_to_relation(select, from_relations, ctx)?;
+    }
+    Ok(())
+}
+
+// Tables other than WITHOUT ROWID ones can be queried by rowid under any of these names
+pub(crate) fn is_rowid_alias(name: &str) -> bool {
+    ["rowid", "oid", "_rowid_"]
+        .iter()
+        .any(|alias| util::ident_eq(alias, name))
 }
 
-fn expression_to_col_name(e: &Expr) -> Result<String, Error> {
+// `source_text` is the expression as written, which is what SQLite names the column after.
+fn expression_to_col_name(
+    e: &Expr,
+    source_text: Option<&String>,
+    ctx: &Context,
+) -> Result<String, Error> {
     match e {
         Expr::Name(Name(name))
         | Expr::Id(Id(name))
         | Expr::Qualified(_, Name(name))
         | Expr::DoublyQualified(_, _, Name(name)) => Ok(util::unquote_ident(name).to_string()),
+        _ if ctx.options.sqlite_column_names || ctx.options.array_mode => {
+            Ok(source_text.cloned().unwrap_or_else(|| expression_text(e)))
+        }
         _ => {
-            let mut collector = TokenCollector { parts: vec![] };
-            e.to_tokens(&mut collector).unwrap(); // TokenCollector always returns Ok
-            Err(Error::Other(format!(
-                "Please alias (as) the expression used as a column ({}) or use array mode",
-                collector.to_string()
-            )))
+            let text = expression_text(e);
+            Err(Error::about(
+                Code::UnaliasedExpression,
+                format!(
+                    "Please alias (as) the expression used as a column ({}) or use array mode",
+                    text
+                ),
+                text,
+            ))
         }
     }
 }
 
-fn resolve_selection_set_expr_type(
+fn expression_text(e: &Expr) -> String {
+    let mut collector = TokenCollector { parts: vec![] };
+    e.to_tokens(&mut collector).unwrap(); // TokenCollector always returns Ok
+    collector.to_string()
+}
+
+// Column references resolve to the referenced column. Anything else is an expression to type.
+fn resolve_selection_set_expr(
     e: &Expr,
-    from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Result<ColType, Error> {
+    from_relations: &[FromRelation],
+    ctx: &Context,
+) -> Result<(Option<ColName>, ColType), Error> {
     match e {
         Expr::Name(Name(name)) | Expr::Id(Id(name)) => {
-            for relation in from_relations {
-                for col in &relation.1 {
-                    if &col.0 == name {
-                        return Ok(col.1.to_vec());
-                    }
-                }
-            }
-            Err(Error::Other(format!(
-                "Could not find selected column {} in from clause",
-                name
-            )))
+            let (_, col) = resolve_unqualified_col(&util::unquote_ident(name), from_relations)?;
+            Ok((Some(col.0.to_string()), col.1.to_vec()))
         }
         Expr::Qualified(Name(table_name), Name(col_name)) => {
-            let prefixed = format!("main.{}", util::unquote_ident(table_name));
+            let table_name = util::unquote_ident(table_name);
             let col_name = util::unquote_ident(col_name);
             for relation in from_relations {
-                if let Some(relation_name) = &relation.0 {
-                    if &prefixed == relation_name {
-                        for col in &relation.1 {
-                            if &col.0 == col_name {
-                                return Ok(col.1.to_vec());
-                            }
-                        }
+                if relation.is_named(None, &table_name) {
+                    if let Some(col) = relation.col(&col_name) {
+                        return Ok((Some(col.0.to_string()), col.1.to_vec()));
                     }
                 }
             }
-            Err(Error::Other(format!(
-                "Could not find selected column {}.{} in from clause",
-                prefixed, col_name
-            )))
+            let candidates = from_relations
+                .iter()
+                .filter(|r| r.is_named(None, &table_name))
+                .flat_map(|r| r.cols.iter().map(|c| c.0.as_str()));
+            Err(Error::no_such_column(
+                Some(&table_name),
+                &col_name,
+                candidates,
+            ))
         }
         Expr::DoublyQualified(Name(db_name), Name(table_name), Name(col_name)) => {
-            let prefixed = format!("{}.{}", db_name, util::unquote_ident(table_name));
+            let db_name = util::unquote_ident(db_name);
+            let table_name = util::unquote_ident(table_name);
             let col_name = util::unquote_ident(col_name);
             for relation in from_relations {
-                if let Some(relation_name) = &relation.0 {
-                    if &prefixed == relation_name {
-                        for col in &relation.1 {
-                            if &col.0 == col_name {
-                                return Ok(col.1.to_vec());
-                            }
-                        }
+                if relation.is_named(Some(&db_name), &table_name) {
+                    if let Some(col) = relation.col(&col_name) {
+                        return Ok((Some(col.0.to_string()), col.1.to_vec()));
                     }
                 }
             }
-            Err(Error::Other(format!(
-                "Could not find selected column {}.{}.{} in from clause",
-                db_name, table_name, col_name
-            )))
+            let candidates = from_relations
+                .iter()
+                .filter(|r| r.is_named(Some(&db_name), &table_name))
+                .flat_map(|r| r.cols.iter().map(|c| c.0.as_str()));
+            Err(Error::no_such_column(
+                Some(&format!("{}.{}", db_name, table_name)),
+                &col_name,
+                candidates,
+            ))
         }
-        _ => expression_to_type(e, from_relations, schema),
+        _ => Ok((None, expression_to_type(e, from_relations, ctx)?)),
     }
 }
 
+// SQLite looks for an unqualified column in the innermost scope first and only moves out if it isn't there.
+// Finding it in more than one relation of a scope is an error unless a USING or NATURAL join merged them.
+// Returns the index of the relation the column was found in along with the column.
+pub(crate) fn resolve_unqualified_col<'a>(
+    name: &str,
+    from_relations: &'a [FromRelation],
+) -> Result<(usize, &'a Col), Error> {
+    let max_depth = from_relations.iter().map(|r| r.depth).max().unwrap_or(0);
+    for depth in 0..=max_depth {
+        let candidates = from_relations
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.depth == depth && !r.merged.iter().any(|m| util::ident_eq(m, name)))
+            .filter_map(|(i, r)| r.unqualified_col(name).map(|c| (i, r, c)))
+            .collect::<Vec<_>>();
+        match candidates.as_slice() {
+            [] => continue,
+            [(i, _, col)] => return Ok((*i, col)),
+            _ => {
+                return Err(Error::ambiguous_column(
+                    name,
+                    candidates
+                        .iter()
+                        .map(|(_, r, c)| format!("{}.{}", r.name.as_deref().unwrap_or("?"), c.0))
+                        .collect(),
+                ))
+            }
+        }
+    }
+    let candidates = from_relations
+        .iter()
+        .flat_map(|r| r.cols.iter().map(|c| c.0.as_str()));
+    Err(Error::no_such_column(None, name, candidates))
+}
+
 fn selection_set(select: &Select) -> Result<Vec<ResultColumn>, Error> {
     // we only care about `OneSelect` and not compounds given unions, intersects, etc. must be compatible relations
     match &select.body.select {
@@ -283,25 +1066,25 @@ fn selection_set(select: &Select) -> Result<Vec<ResultColumn>, Error> {
 // a vec of relations since many relations can be joined in.
 // the returned vec of relations might copy relations from the schema but change their col types due to left vs right vs inner join.
 fn from_relations(
-    select: &Select,
-    outer_from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Result<Vec<Relation>, Error> {
-    match &select.body.select {
+    select: &OneSelect,
+    outer_from_relations: &[FromRelation],
+    ctx: &Context,
+) -> Result<Vec<FromRelation>, Error> {
+    match select {
         OneSelect::Select {
             from: Some(FromClause { select, joins, .. }),
             ..
-        } => relations_from_from_clause(select, joins, outer_from_relations, schema),
+        } => relations_from_from_clause(select, joins, outer_from_relations, ctx),
         OneSelect::Values(vals) => {
             // vals are all literal expressions...
             // cols are just numbered `columnN...`
             // the relation is unnamed and unaliased
             if let Some(first) = vals.first() {
                 let namer = |i: usize, _e: &Expr| -> String { format!("column{}", i) };
-                Ok(vec![(
-                    None,
-                    expressions_to_columns(first, namer, outer_from_relations, schema)?,
-                )])
+                Ok(vec![FromRelation {
+                    cols: expressions_to_columns(first, namer, outer_from_relations, ctx)?,
+                    ..FromRelation::default()
+                }])
             } else {
                 Err(Error::Other(
                     "nothing was selected in the select statement".to_string(),
@@ -315,98 +1098,163 @@ fn from_relations(
 fn relations_from_from_clause(
     select: &Option<Box<SelectTable>>,
     joins: &Option<Vec<JoinedSelectTable>>,
-    outer_from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Result<Vec<Relation>, Error> {
+    outer_from_relations: &[FromRelation],
+    ctx: &Context,
+) -> Result<Vec<FromRelation>, Error> {
     // join type changes nullability!
     let mut ret = vec![];
     if let Some(selectable) = select {
         ret.push(relation_from_selecttable(
             selectable,
             outer_from_relations,
-            schema,
+            ctx,
         )?);
     }
     if let Some(join_selectables) = joins {
-        // for each join --
-        // we'd need to go back and mutate the prior relation if the join is a left join.
-        // if a right join, update relation as pushed.
         for selectable in join_selectables {
-            match selectable.operator {
-                JoinOperator::Comma => ret.push(relation_from_selecttable(
-                    &selectable.table,
-                    outer_from_relations,
-                    schema,
-                )?),
-                JoinOperator::TypedJoin {
-                    join_type: None, ..
-                } => ret.push(relation_from_selecttable(
-                    &selectable.table,
-                    outer_from_relations,
-                    schema,
-                )?),
+            let joined = relation_from_selecttable(&selectable.table, outer_from_relations, ctx)?;
+            let merged = merged_col_names(selectable, &ret, &joined)?;
+            // whether rows of the relations to the left and of the joined relation can go unmatched
+            let (left_nullable, right_nullable) = match selectable.operator {
                 JoinOperator::TypedJoin {
-                    join_type: Some(JoinType::Inner),
+                    join_type: Some(JoinType::Left | JoinType::LeftOuter),
                     ..
-                } => ret.push(relation_from_selecttable(
-                    &selectable.table,
-                    outer_from_relations,
-                    schema,
-                )?),
+                } => (false, true),
                 JoinOperator::TypedJoin {
-                    join_type: Some(JoinType::Cross),
+                    join_type: Some(JoinType::Right | JoinType::RightOuter),
                     ..
-                } => ret.push(relation_from_selecttable(
-                    &selectable.table,
-                    outer_from_relations,
-                    schema,
-                )?),
+                } => (true, false),
                 JoinOperator::TypedJoin {
-                    join_type: Some(JoinType::Left),
+                    join_type: Some(JoinType::Full | JoinType::FullOuter),
                     ..
-                } => handle_left_join(&mut ret, &selectable.table, outer_from_relations, schema)?,
-                JoinOperator::TypedJoin {
-                    join_type: Some(JoinType::LeftOuter),
-                    ..
-                } => handle_left_join(&mut ret, &selectable.table, outer_from_relations, schema)?,
-                JoinOperator::TypedJoin {
-                    join_type: Some(JoinType::Right),
-                    ..
-                } => handle_right_join(&mut ret, &selectable.table, outer_from_relations, schema)?,
-                JoinOperator::TypedJoin {
-                    join_type: Some(JoinType::RightOuter),
-                    ..
-                } => handle_right_join(&mut ret, &selectable.table, outer_from_relations, schema)?,
-                JoinOperator::TypedJoin {
-                    join_type: Some(JoinType::Full),
-                    ..
-                } => handle_full_join(&mut ret, &selectable.table, outer_from_relations, schema)?,
-                JoinOperator::TypedJoin {
-                    join_type: Some(JoinType::FullOuter),
-                    ..
-                } => handle_full_join(&mut ret, &selectable.table, outer_from_relations, schema)?,
+                } => (true, true),
+                _ => (false, false),
+            };
+
+            // An unqualified merged column is `coalesce(left.col, right.col)` once rows on the left can go unmatched.
+            // https://www.sqlite.org/lang_select.html#the_from_clause
+            let mut coalesced = vec![];
+            if left_nullable {
+                for name in &merged {
+                    let Some(owner) = ret.iter().position(|r| {
+                        r.col(name).is_some() && !r.merged.iter().any(|m| util::ident_eq(m, name))
+                    }) else {
+                        continue;
+                    };
+                    let left = ret[owner].unqualified_col(name).unwrap();
+                    let right = joined.col(name).unwrap();
+                    coalesced.push((owner, coalesce(left, right, right_nullable)));
+                }
+                ret = ret.into_iter().map(make_all_cols_nullable).collect();
+            }
+            for (owner, col) in coalesced {
+                let relation = &mut ret[owner];
+                relation.coalesced.retain(|c| !util::ident_eq(&c.0, &col.0));
+                relation.coalesced.push(col);
+            }
+
+            let joined = FromRelation { merged, ..joined };
+            ret.push(if right_nullable {
+                make_all_cols_nullable(joined)
+            } else {
+                joined
+            });
+            if let Some(JoinConstraint::On(on)) = &selectable.constraint {
+                // the ON clause sees the relations joined so far and those of enclosing selects
+                let scope = ret
+                    .iter()
+                    .cloned()
+                    .chain(outer_from_relations.iter().map(|r| FromRelation {
+                        depth: r.depth + 1,
+                        ..r.clone()
+                    }))
+                    .collect::<Vec<_>>();
+                check_names(on, &scope, &[], ctx)?;
+                // only an inner join drops the rows its ON clause rejects
+                if !left_nullable && !right_nullable {
+                    nullability::narrow(&mut ret, on);
+                }
             }
         }
     }
     Ok(ret)
 }
 
-fn make_all_cols_nullable(relation: Relation) -> Relation {
-    (
-        relation.0,
-        relation
-            .1
+// The type of `coalesce(left, right)` for a column merged by a RIGHT or FULL join.
+// Joined rows matched on the column so it is only NULL where an unmatched row's own column is.
+fn coalesce(left: &Col, right: &Col, left_unmatched: bool) -> Col {
+    let is_null = |t: &(TypeKind, Option<BuiltinType>, Option<String>)| {
+        matches!(t.1, Some(BuiltinType::Null))
+    };
+    let mut col_type: ColType = left.1.iter().filter(|t| !is_null(t)).cloned().collect();
+    for t in right.1.iter().filter(|t| !is_null(t)) {
+        if !col_type.contains(t) {
+            col_type.push(t.clone());
+        }
+    }
+    if right.1.iter().any(is_null) || (left_unmatched && left.1.iter().any(is_null)) {
+        col_type.extend(builtin_type(BuiltinType::Null));
+    }
+    (left.0.to_string(), col_type)
+}
+
+// The columns of `right` that a USING or NATURAL join merges with a column of a relation to its left.
+// https://www.sqlite.org/syntax/join-constraint.html
+fn merged_col_names(
+    join: &JoinedSelectTable,
+    left: &[FromRelation],
+    right: &FromRelation,
+) -> Result<Vec<ColName>, Error> {
+    let in_left = |name: &str| left.iter().any(|r| r.col(name).is_some());
+    match (&join.operator, &join.constraint) {
+        (_, Some(JoinConstraint::Using(names))) => names
+            .iter()
+            .map(|Name(name)| {
+                let name = util::unquote_ident(name);
+                match right.col(&name) {
+                    Some(col) if in_left(&name) => Ok(col.0.to_string()),
+                    _ => Err(Error::about(
+                        Code::JoinColumn,
+                        format!(
+                            "cannot join using column {} - column not present in both tables",
+                            name
+                        ),
+                        name.to_string(),
+                    )),
+                }
+            })
+            .collect(),
+        (JoinOperator::TypedJoin { natural: true, .. }, _) => Ok(right
+            .star_cols()
+            .into_iter()
+            .filter(|c| in_left(&c.0))
+            .map(|c| c.0)
+            .collect()),
+        _ => Ok(vec![]),
+    }
+}
+
+fn make_all_cols_nullable(relation: FromRelation) -> FromRelation {
+    FromRelation {
+        cols: relation
+            .cols
+            .iter()
+            .map(|c| (c.0.to_string(), make_type_nullable(c.1.clone())))
+            .collect(),
+        coalesced: relation
+            .coalesced
             .iter()
             .map(|c| (c.0.to_string(), make_type_nullable(c.1.clone())))
             .collect(),
-    )
+        matched_cols: relation.matched_cols.or(Some(relation.cols)),
+        ..relation
+    }
 }
 
 fn make_type_nullable(mut t: ColType) -> ColType {
-    if t.iter().any(|(_, maybe_builtin, _)| match maybe_builtin {
-        Some(BuiltinType::Null) => true,
-        _ => false,
-    }) {
+    if t.iter()
+        .any(|(_, maybe_builtin, _)| matches!(maybe_builtin, Some(BuiltinType::Null)))
+    {
         t
     } else {
         t.extend(builtin_type(BuiltinType::Null));
@@ -414,90 +1262,41 @@ fn make_type_nullable(mut t: ColType) -> ColType {
     }
 }
 
-fn handle_full_join(
-    ret: &mut Vec<Relation>,
-    selectable: &SelectTable,
-    outer_from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Result<(), Error> {
-    if let Some(last_relation) = ret.pop() {
-        ret.push(make_all_cols_nullable(last_relation));
-    }
-    ret.push(make_all_cols_nullable(relation_from_selecttable(
-        selectable,
-        outer_from_relations,
-        schema,
-    )?));
-    Ok(())
-}
-
-fn handle_right_join(
-    ret: &mut Vec<Relation>,
-    selectable: &SelectTable,
-    outer_from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Result<(), Error> {
-    if let Some(last_relation) = ret.pop() {
-        ret.push(make_all_cols_nullable(last_relation));
-    }
-    ret.push(relation_from_selecttable(
-        selectable,
-        outer_from_relations,
-        schema,
-    )?);
-    Ok(())
-}
-
-fn handle_left_join(
-    ret: &mut Vec<Relation>,
-    selectable: &SelectTable,
-    outer_from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Result<(), Error> {
-    ret.push(make_all_cols_nullable(relation_from_selecttable(
-        selectable,
-        outer_from_relations,
-        schema,
-    )?));
-    Ok(())
-}
-
 fn relation_from_selecttable(
     selectable: &SelectTable,
-    from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Result<Relation, Error> {
+    from_relations: &[FromRelation],
+    ctx: &Context,
+) -> Result<FromRelation, Error> {
     match selectable {
-        SelectTable::Table(qualified_name, maybe_as, _) => Ok(maybe_aliased_table_to_relation(
-            qualified_name,
-            maybe_as,
-            schema,
-        )),
-        SelectTable::TableCall(qualified_name, _, maybe_as) => Ok(maybe_aliased_table_to_relation(
-            qualified_name,
-            maybe_as,
-            schema,
-        )),
+        SelectTable::Table(qualified_name, maybe_as, _) => {
+            maybe_aliased_table_to_relation(qualified_name, maybe_as, ctx)
+        }
+        SelectTable::TableCall(qualified_name, _, maybe_as) => {
+            maybe_aliased_table_to_relation(qualified_name, maybe_as, ctx)
+        }
         SelectTable::Select(select, maybe_as) => {
-            let relation = select_to_relation(select, from_relations, schema)?;
-            if let Some(as_) = maybe_as {
-                Ok((Some(format!("main.{}", extract_alias(as_))), relation.1))
-            } else {
-                Ok(relation)
-            }
+            let ((_, cols), origins) =
+                select_to_relation_with_origins(select, from_relations, ctx)?;
+            Ok(FromRelation {
+                name: maybe_as.as_ref().map(|as_| extract_alias(as_).to_string()),
+                cols,
+                origins,
+                ..FromRelation::default()
+            })
         }
         SelectTable::Sub(from, maybe_as) => {
             // a sub yields 1 relation which is the selection set against the provided relations of the sub-query
             // idk, sub doesn't really make sense to me here. Select arm should already cover this.
             let relations =
-                relations_from_from_clause(&from.select, &from.joins, from_relations, schema)?;
+                relations_from_from_clause(&from.select, &from.joins, from_relations, ctx)?;
             // TODO: what if many relations in this position?
             if let Some(first) = relations.first() {
                 if let Some(as_) = maybe_as {
-                    Ok((
-                        Some(format!("main.{}", extract_alias(as_))),
-                        first.1.to_vec(),
-                    ))
+                    Ok(FromRelation {
+                        db: None,
+                        name: Some(extract_alias(as_).to_string()),
+                        ..first.clone()
+                    })
                 } else {
                     Ok(first.clone())
                 }
@@ -510,64 +1309,139 @@ fn relation_from_selecttable(
     }
 }
 
-fn maybe_aliased_table_to_relation(
+pub(crate) fn maybe_aliased_table_to_relation(
     qualified_name: &QualifiedName,
     maybe_as: &Option<As>,
-    schema: &HashMap<RelationName, Vec<Col>>,
-) -> Relation {
-    if let Some(as_) = maybe_as {
-        let alias = extract_alias(as_);
-        let canonical_name = normalize_qualified_name(qualified_name);
-        let cols = schema
-            .get(&canonical_name)
-            .map_or(vec![], |f| -> Vec<Col> { f.to_vec() });
-        (Some(format!("main.{}", alias)), cols)
+    ctx: &Context,
+) -> Result<FromRelation, Error> {
+    let db_name = qualified_name
+        .db_name
+        .as_ref()
+        .map(|n| util::unquote_ident(&n.0));
+    let table_name = util::unquote_ident(&qualified_name.name.0);
+    let cte = match db_name {
+        Some(_) => None,
+        None => ctx.ctes.iter().find(|r| r.is_named(None, &table_name)),
+    };
+    let (db, (cols, info), origins) = match (cte, ctx.schema.get(db_name.as_deref(), &table_name)) {
+        (Some(cte), _) => (
+            None,
+            (cte.cols.clone(), cte.info.clone()),
+            cte.origins.clone(),
+        ),
+        (None, Some((db, relation))) => {
+            let (origin_db, origin_table) = ctx
+                .schema
+                .declared_name(db, &table_name)
+                .unwrap_or((db, &table_name));
+            let origins = relation
+                .0
+                .iter()
+                .map(|(col_name, _)| {
+                    Some(ColumnOrigin {
+                        db: origin_db.to_string(),
+                        table: origin_table.to_string(),
+                        column: col_name.to_string(),
+                    })
+                })
+                .collect();
+            (Some(db.to_string()), relation.clone(), origins)
+        }
+        (None, None) => return Err(no_such_table(db_name.as_deref(), &table_name, ctx)),
+    };
+    if let Some(as_) = maybe_as {
+        Ok(FromRelation {
+            db: None,
+            name: Some(extract_alias(as_).to_string()),
+            cols,
+            info,
+            origins,
+            ..FromRelation::default()
+        })
     } else {
-        let canonical_name = normalize_qualified_name(qualified_name);
-        let cols = schema
-            .get(&canonical_name)
-            .map_or(vec![], |f| -> Vec<Col> { f.to_vec() });
-        (Some(canonical_name), cols)
+        Ok(FromRelation {
+            db,
+            name: Some(table_name.to_string()),
+            cols,
+            info,
+            origins,
+            ..FromRelation::default()
+        })
     }
 }
 
-fn extract_alias(as_: &As) -> &str {
+fn no_such_table(db: Option<&str>, name: &str, ctx: &Context) -> Error {
+    let mut candidates = ctx.schema.relation_names(db);
+    if db.is_none() {
+        candidates.extend(ctx.ctes.iter().filter_map(|r| r.name.as_deref()));
+    }
+    Error::no_such_table(db, name, candidates)
+}
+
+fn extract_alias(as_: &As) -> Cow<'_, str> {
     match as_ {
         As::As(n) => util::unquote_ident(&n.0),
         As::Elided(n) => util::unquote_ident(&n.0),
     }
 }
 
-fn normalize_qualified_name(name: &QualifiedName) -> String {
-    let db_name = &name.db_name;
-    format!(
-        "{}.{}",
-        if let Some(name) = db_name {
-            name.0.to_string()
-        } else {
-            "main".to_string()
-        },
-        name.name.0,
-    )
-}
-
-fn with_relations(_select: &Select) -> HashMap<RelationName, Vec<Col>> {
-    // TODO: impl with_relations
-    HashMap::new()
+// The common table expressions a select can refer to. Each one can refer to those before it.
+// A recursive one is typed by its initial select since the recursive part refers back to it.
+// https://www.sqlite.org/lang_with.html
+pub(crate) fn with_relations(
+    select: &Select,
+    outer_from_relations: &[FromRelation],
+    ctx: &Context,
+) -> Result<Vec<FromRelation>, Error> {
+    let mut ret = ctx.ctes.to_vec();
+    for cte in select.with.iter().flat_map(|with| &with.ctes) {
+        let name = util::unquote_ident(&cte.tbl_name.0).to_string();
+        let ((_, mut cols), origins) = select_to_relation_with_origins(
+            &cte.select,
+            outer_from_relations,
+            &Context { ctes: &ret, ..*ctx },
+        )?;
+        if let Some(columns) = &cte.columns {
+            if columns.len() != cols.len() {
+                return Err(Error::coded(
+                    Code::ColumnCount,
+                    format!(
+                        "table {} has {} values for {} columns",
+                        name,
+                        cols.len(),
+                        columns.len()
+                    ),
+                ));
+            }
+            for (col, column) in cols.iter_mut().zip(columns) {
+                col.0 = util::unquote_ident(&column.col_name.0).to_string();
+            }
+        }
+        ret.insert(
+            0,
+            FromRelation {
+                name: Some(name),
+                cols,
+                origins,
+                ..FromRelation::default()
+            },
+        );
+    }
+    Ok(ret)
 }
 
 fn expressions_to_columns<F: Fn(usize, &Expr) -> String>(
-    expressions: &Vec<Expr>,
+    expressions: &[Expr],
     namer: F,
-    from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
+    from_relations: &[FromRelation],
+    ctx: &Context,
 ) -> Result<Vec<Col>, Error> {
     let mut err = Ok(());
     let ret = expressions
         .iter()
         .enumerate()
         .map(|(i, e)| -> Col {
-            match expression_to_column(i, e, &namer, from_relations, schema) {
+            match expression_to_column(i, e, &namer, from_relations, ctx) {
                 Ok(c) => c,
                 Err(e) => {
                     err = Err(e);
@@ -578,58 +1452,67 @@ fn expressions_to_columns<F: Fn(usize, &Expr) -> String>(
         })
         .collect::<Vec<_>>();
 
-    if let Err(e) = err {
-        return Err(e);
-    }
-    return Ok(ret);
+    err?;
+    Ok(ret)
 }
 
 fn expression_to_column<F: Fn(usize, &Expr) -> String>(
     i: usize,
     expression: &Expr,
     namer: &F,
-    from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
+    from_relations: &[FromRelation],
+    ctx: &Context,
 ) -> Result<Col, Error> {
     let col_name = namer(i, expression);
-    let col_type = expression_to_type(expression, from_relations, schema)?;
+    let col_type = expression_to_type(expression, from_relations, ctx)?;
     Ok((col_name, col_type))
 }
 
 // TODO: should this not have access to from_relations if it exists?
 fn expression_to_type(
     expression: &Expr,
-    from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
+    from_relations: &[FromRelation],
+    ctx: &Context,
 ) -> Result<ColType, Error> {
     match expression {
         Expr::Binary(_, op, _) => Ok(op_to_type(op)),
         Expr::Case {
             when_then_pairs, ..
-        } => when_then_to_type(when_then_pairs, from_relations, schema),
-        Expr::Cast { type_name, .. } => Ok(type_from_type_name(type_name.name.to_string())),
+        } => when_then_to_type(when_then_pairs, from_relations, ctx),
+        Expr::Cast { type_name, .. } => Ok(type_from_declared_type(type_name, false, ctx.options)),
         // DoublyQualified would be processed when the col name is returned then married against relations on which it is applied
         // None type returned at this point since we don't have full information
         Expr::Exists(_) => Ok(builtin_type(BuiltinType::Boolean)),
         Expr::FunctionCall {
             name: Id(n), args, ..
-        } => Ok(fn_call_to_type(n, args)),
+        } => {
+            if is_fts5_auxiliary_fn(n) {
+                check_fts5_table_arg(n, args, from_relations)?;
+            }
+            Ok(fn_call_to_type(n, args))
+        }
         Expr::FunctionCallStar { name: Id(n), .. } => Ok(fn_call_to_type(n, &None)),
-        Expr::Id(_) => Ok(vec![]), // unresolved type. Will get resolved in a later step
         Expr::InList { .. } => Ok(builtin_type(BuiltinType::Boolean)),
         Expr::InSelect { .. } => Ok(builtin_type(BuiltinType::Boolean)),
         Expr::InTable { .. } => Ok(builtin_type(BuiltinType::Boolean)),
         Expr::IsNull { .. } => Ok(builtin_type(BuiltinType::Boolean)),
         Expr::Like { .. } => Ok(builtin_type(BuiltinType::Boolean)),
         Expr::Literal(lit) => Ok(literal_to_type(lit)),
-        Expr::Name(_) => Ok(vec![]), // unresolved type. Will get resolved in a later step.
         Expr::NotNull { .. } => Ok(builtin_type(BuiltinType::Boolean)),
-        Expr::Parenthesized(expr) => subexpression_to_type(expr, from_relations, schema),
-        Expr::Qualified(_, _) => Ok(vec![]),
-        Expr::DoublyQualified(_, _, _) => Ok(vec![]),
-        Expr::Subquery(select) => subquery_to_type(select, from_relations, schema), // a subquery in this position can only return 1 row 1 col
+        Expr::Parenthesized(expr) => subexpression_to_type(expr, from_relations, ctx),
+        Expr::Subquery(select) => subquery_to_type(select, from_relations, ctx), // a subquery in this position can only return 1 row 1 col
         Expr::Unary(op, _) => Ok(unary_op_to_type(op)),
-        _ => Ok(vec![]),
+        Expr::Between { .. } => Ok(builtin_type(BuiltinType::Boolean)),
+        Expr::Collate(e, _) => expression_to_type(e, from_relations, ctx),
+        // column references in nested positions, like `(a)`
+        Expr::Id(_) | Expr::Name(_) | Expr::Qualified(..) | Expr::DoublyQualified(..) => {
+            Ok(resolve_selection_set_expr(expression, from_relations, ctx)?.1)
+        }
+        Expr::Variable(_) => Ok(unresolved_type(format!(
+            "the type of parameter {} is whatever is bound to it",
+            expression_text(expression)
+        ))),
+        Expr::Raise(..) => Ok(unresolved_type("RAISE() has no value".to_string())),
     }
 }
 
@@ -672,12 +1555,12 @@ fn unary_op_to_type(op: &UnaryOperator) -> ColType {
 }
 
 fn when_then_to_type(
-    when_then_pairs: &Vec<(Expr, Expr)>,
-    from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
+    when_then_pairs: &[(Expr, Expr)],
+    from_relations: &[FromRelation],
+    ctx: &Context,
 ) -> Result<ColType, Error> {
     if let Some(when_then) = when_then_pairs.first() {
-        expression_to_type(&when_then.1, from_relations, schema)?;
+        expression_to_type(&when_then.1, from_relations, ctx)?;
     }
     Err(Error::Other(
         "Found a WHEN statement without a THEN".to_string(),
@@ -686,7 +1569,7 @@ fn when_then_to_type(
 
 // Type needs to be more than a string given nullability is involved.
 // It doesn't need to be option given we have `any`
-fn fn_call_to_type(fn_name: &String, _args: &Option<Vec<Expr>>) -> ColType {
+fn fn_call_to_type(fn_name: &str, _args: &Option<Vec<Expr>>) -> ColType {
     let lowered = fn_name.to_lowercase();
     if lowered == "abs" {
         builtin_type(BuiltinType::Number)
@@ -694,10 +1577,12 @@ fn fn_call_to_type(fn_name: &String, _args: &Option<Vec<Expr>>) -> ColType {
         || lowered == "format"
         || lowered == "glob"
         || lowered == "hex"
+        || lowered == "highlight"
         || lowered == "lower"
         || lowered == "ltrim"
         || lowered == "printf"
         || lowered == "rtrim"
+        || lowered == "snippet"
         || lowered == "soundex"
         || lowered == "sqlite_compileoption_get"
         || lowered == "sqlite_source_id"
@@ -716,10 +1601,16 @@ fn fn_call_to_type(fn_name: &String, _args: &Option<Vec<Expr>>) -> ColType {
         || lowered == "nullif"
     {
         // type is union of arguments and null
-        vec![]
+        unresolved_type(format!(
+            "the type of {}() depends on its arguments",
+            fn_name
+        ))
     } else if lowered == "iif" {
         // TODO - type is union of arguments
-        vec![]
+        unresolved_type(format!(
+            "the type of {}() depends on its arguments",
+            fn_name
+        ))
     } else if lowered == "quote" {
         // TODO: we could take the args to quote and return Quoted<GENERIC>
         // so ColType should have a palceholder for generics?
@@ -741,53 +1632,64 @@ fn fn_call_to_type(fn_name: &String, _args: &Option<Vec<Expr>>) -> ColType {
         || lowered == "count"
     {
         builtin_type(BuiltinType::Int)
-    } else if lowered == "round" {
+    } else if lowered == "round" || lowered == "bm25" {
         builtin_type(BuiltinType::Float)
     } else if lowered == "sqlite_compileoption_used" {
         builtin_type(BuiltinType::Boolean)
     } else {
-        vec![]
+        unresolved_type(format!("unknown function {}()", fn_name))
     }
 }
 
-fn literal_to_type(lit: &Literal) -> ColType {
-    match lit {
-        Literal::Numeric(l) => vec![(
-            TypeKind::Literal,
-            Some(BuiltinType::Number),
-            Some(l.to_string()),
-        )],
-        Literal::String(l) => vec![(
-            TypeKind::Literal,
-            Some(BuiltinType::String),
-            Some(l.to_string()),
-        )],
-        Literal::Blob(l) => vec![(
-            TypeKind::Literal,
-            Some(BuiltinType::Blob),
-            Some(l.to_string()),
-        )],
-        // TODO: what does it mean for a keyword literal to exist in a position the should result in a value?
-        Literal::Keyword(l) => vec![(TypeKind::Literal, None, Some(l.to_string()))],
-        Literal::Null => builtin_type(BuiltinType::Null),
-        Literal::CurrentDate => vec![(TypeKind::Literal, Some(BuiltinType::CurrentDate), None)],
-        Literal::CurrentTime => vec![(TypeKind::Literal, Some(BuiltinType::CurrentTime), None)],
-        Literal::CurrentTimestamp => {
-            vec![(TypeKind::Literal, Some(BuiltinType::CurrentTimestamp), None)]
+// https://www.sqlite.org/fts5.html#auxiliary_functions
+fn is_fts5_auxiliary_fn(fn_name: &str) -> bool {
+    ["bm25", "highlight", "snippet"]
+        .iter()
+        .any(|f| f.eq_ignore_ascii_case(fn_name))
+}
+
+// Auxiliary functions take the FTS5 table being queried as their first argument.
+// SQLite only catches a wrong one when the query runs.
+fn check_fts5_table_arg(
+    fn_name: &str,
+    args: &Option<Vec<Expr>>,
+    from_relations: &[FromRelation],
+) -> Result<(), Error> {
+    let table_name = match args.as_deref() {
+        Some([Expr::Id(Id(name)) | Expr::Name(Name(name)), ..]) => util::unquote_ident(name),
+        _ => {
+            return Err(Error::Other(format!(
+                "The first argument to {} must be an FTS5 table",
+                fn_name
+            )))
         }
+    };
+    match from_relations
+        .iter()
+        .find(|r| r.is_named(None, &table_name))
+    {
+        Some(relation) if relation.info.module.as_deref() == Some("fts5") => Ok(()),
+        Some(_) => Err(Error::Other(format!(
+            "{} is not an FTS5 table so it can't be passed to {}",
+            table_name, fn_name
+        ))),
+        None => Err(Error::Other(format!(
+            "{} must be passed an FTS5 table from the FROM clause but {} is not in the FROM clause",
+            fn_name, table_name
+        ))),
     }
 }
 
 fn subexpression_to_type(
-    expressions: &Vec<Expr>,
-    from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
+    expressions: &[Expr],
+    from_relations: &[FromRelation],
+    ctx: &Context,
 ) -> Result<ColType, Error> {
     // ok, this is weird that it is an array of expressions to refer to a sub-expression.
     // it seems like there should only ever be one sub-expression if this appears in a position that can emit a result type.
     // TODO: error on many expressions?
     if let Some(e) = expressions.first() {
-        expression_to_type(e, from_relations, schema)
+        expression_to_type(e, from_relations, ctx)
     } else {
         Err(Error::Other("Missing expression".to_string()))
     }
@@ -795,11 +1697,11 @@ fn subexpression_to_type(
 
 // A subquery in a type position can only return a single column so it has a single type.
 fn subquery_to_type(
-    query: &Box<Select>,
-    outer_from_relations: &Vec<Relation>,
-    schema: &HashMap<RelationName, Vec<Col>>,
+    query: &Select,
+    outer_from_relations: &[FromRelation],
+    ctx: &Context,
 ) -> Result<ColType, Error> {
-    let subquery_relation = select_to_relation(query, outer_from_relations, schema)?;
+    let subquery_relation = select_to_relation(query, outer_from_relations, ctx)?;
     // TODO: error on many columns?
     if let Some(col) = subquery_relation.1.first() {
         Ok(col.1.to_vec())
@@ -813,6 +1715,7 @@ fn subquery_to_type(
 #[cfg(test)]
 mod tests {
     use crate::ddl;
+    use crate::options::Options;
 
     use super::*;
 
@@ -825,16 +1728,25 @@ mod tests {
 
     #[test]
     fn select_count() {
-        let schema_shapes = ddl::get_relation_shapes("CREATE TABLE foo (a);".to_string()).unwrap();
-        let schema: HashMap<_, _> = schema_shapes.into_iter().collect();
-        let query_shapes =
-            get_result_shapes("SELECT count(*) FROM foo".to_string(), schema).unwrap();
+        let schema_shapes =
+            ddl::get_relation_shapes("CREATE TABLE foo (a);".to_string(), &Options::default())
+                .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let query_shapes = get_result_shapes(
+            "SELECT count(*) FROM foo".to_string(),
+            schema,
+            &Options {
+                sqlite_column_names: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
         assert_eq!(
             query_shapes,
             vec![(
                 None,
                 vec![(
-                    "count".to_string(),
+                    "count(*)".to_string(),
                     vec![(TypeKind::Builtin, Some(BuiltinType::Int), None)]
                 )]
             )]
@@ -843,11 +1755,16 @@ mod tests {
 
     #[test]
     fn select_star_single_table_nullable() {
-        let schema_shapes =
-            ddl::get_relation_shapes("CREATE TABLE foo (a INTEGER, b TEXT);".to_string()).unwrap();
-        let schema: HashMap<_, _> = schema_shapes.into_iter().collect();
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE foo (a INTEGER, b TEXT);".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
 
-        let query_shapes = get_result_shapes("SELECT * FROM foo".to_string(), schema).unwrap();
+        let query_shapes =
+            get_result_shapes("SELECT * FROM foo".to_string(), schema, &Options::default())
+                .unwrap();
         assert_eq!(
             query_shapes,
             vec![(
@@ -876,11 +1793,14 @@ mod tests {
     fn select_start_single_table_not_null() {
         let schema_shapes = ddl::get_relation_shapes(
             "CREATE TABLE foo (a INTEGER NOT NULL, b TEXT NOT NULL);".to_string(),
+            &Options::default(),
         )
         .unwrap();
-        let schema: HashMap<_, _> = schema_shapes.into_iter().collect();
+        let schema = Schema::from_relations(schema_shapes);
 
-        let query_shapes = get_result_shapes("SELECT * FROM foo".to_string(), schema).unwrap();
+        let query_shapes =
+            get_result_shapes("SELECT * FROM foo".to_string(), schema, &Options::default())
+                .unwrap();
         assert_eq!(
             query_shapes,
             vec![(
@@ -903,11 +1823,17 @@ mod tests {
     fn select_named_single_table() {
         let schema_shapes = ddl::get_relation_shapes(
             "CREATE TABLE foo (a INTEGER NOT NULL, b TEXT NOT NULL);".to_string(),
+            &Options::default(),
         )
         .unwrap();
-        let schema: HashMap<_, _> = schema_shapes.into_iter().collect();
+        let schema = Schema::from_relations(schema_shapes);
 
-        let query_shapes = get_result_shapes("SELECT a, b FROM foo".to_string(), schema).unwrap();
+        let query_shapes = get_result_shapes(
+            "SELECT a, b FROM foo".to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap();
         assert_eq!(
             query_shapes,
             vec![(
@@ -930,11 +1856,17 @@ mod tests {
     fn select_named_reverse_order_single_tabe() {
         let schema_shapes = ddl::get_relation_shapes(
             "CREATE TABLE foo (a INTEGER NOT NULL, b TEXT NOT NULL);".to_string(),
+            &Options::default(),
         )
         .unwrap();
-        let schema: HashMap<_, _> = schema_shapes.into_iter().collect();
+        let schema = Schema::from_relations(schema_shapes);
 
-        let query_shapes = get_result_shapes("SELECT b, a FROM foo".to_string(), schema).unwrap();
+        let query_shapes = get_result_shapes(
+            "SELECT b, a FROM foo".to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap();
         assert_eq!(
             query_shapes,
             vec![(
@@ -960,12 +1892,17 @@ mod tests {
             CREATE TABLE bar (c ANY NOT NULL, d ANY NOT NULL);
             CREATE TABLE baz (e NOT NULL, f NOT NULL);"
                 .to_string(),
+            &Options::default(),
         )
         .unwrap();
-        let schema: HashMap<_, _> = schema_shapes.into_iter().collect();
+        let schema = Schema::from_relations(schema_shapes);
 
-        let query_shapes =
-            get_result_shapes("SELECT * FROM foo JOIN bar JOIN baz".to_string(), schema).unwrap();
+        let query_shapes = get_result_shapes(
+            "SELECT * FROM foo JOIN bar JOIN baz".to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap();
         assert_eq!(
             query_shapes,
             vec![(
@@ -1000,6 +1937,1331 @@ mod tests {
         )
     }
 
+    #[test]
+    fn cast_uses_type_mappings() {
+        let options = Options {
+            type_mappings: vec![("TIMESTAMP".to_string(), BuiltinType::Int)],
+            ..Default::default()
+        };
+        let query_shapes = get_result_shapes(
+            "SELECT CAST('2020-01-01' AS TIMESTAMP) AS t".to_string(),
+            Schema::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            query_shapes,
+            vec![(
+                None,
+                vec![("t".to_string(), builtin_type(BuiltinType::Int))]
+            )]
+        )
+    }
+
+    #[test]
+    fn converters_for_custom_and_decoded_types() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE foo (a INTEGER, b BOOLEAN, c JSON NOT NULL, d INTERNATIONAL, e DATE);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+
+        let shapes =
+            analyze_query("SELECT * FROM foo".to_string(), schema, &Options::default()).unwrap();
+        assert_eq!(
+            shapes[0].converters,
+            vec![
+                Converter {
+                    position: 1,
+                    type_name: "Boolean".to_string(),
+                    affinity: Affinity::Numeric
+                },
+                Converter {
+                    position: 2,
+                    type_name: "Json".to_string(),
+                    affinity: Affinity::Numeric
+                },
+                Converter {
+                    position: 3,
+                    type_name: "INTERNATIONAL".to_string(),
+                    affinity: Affinity::Integer
+                },
+                Converter {
+                    position: 4,
+                    type_name: "Date".to_string(),
+                    affinity: Affinity::Numeric
+                },
+            ]
+        );
+
+        // the affinity comes from the declared type, not the type it was mapped to
+        let options = Options {
+            type_mappings: vec![
+                ("JSON_TEXT".to_string(), BuiltinType::Json),
+                ("FLAG_INT".to_string(), BuiltinType::Boolean),
+            ],
+            ..Options::default()
+        };
+        let schema = Schema::from_relations(
+            ddl::get_relation_shapes(
+                "CREATE TABLE bar (a JSON_TEXT, b FLAG_INT);".to_string(),
+                &options,
+            )
+            .unwrap(),
+        );
+        let shapes = analyze_query(
+            "SELECT a, b, CAST(a AS JSON_TEXT) AS c FROM bar".to_string(),
+            schema,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            shapes[0]
+                .converters
+                .iter()
+                .map(|c| c.affinity)
+                .collect::<Vec<_>>(),
+            vec![Affinity::Text, Affinity::Integer, Affinity::Numeric]
+        );
+    }
+
+    #[test]
+    fn fts5_hidden_columns() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE VIRTUAL TABLE docs USING fts5(title, body);".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+
+        let query_shapes = get_result_shapes(
+            "SELECT * FROM docs; SELECT d.*, rank FROM docs AS d".to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap();
+        let mut nullable_string = builtin_type(BuiltinType::String);
+        nullable_string.extend(builtin_type(BuiltinType::Null));
+        let mut nullable_float = builtin_type(BuiltinType::Float);
+        nullable_float.extend(builtin_type(BuiltinType::Null));
+        assert_eq!(
+            query_shapes,
+            vec![
+                (
+                    None,
+                    vec![
+                        ("title".to_string(), nullable_string.clone()),
+                        ("body".to_string(), nullable_string.clone()),
+                    ]
+                ),
+                (
+                    None,
+                    vec![
+                        ("title".to_string(), nullable_string.clone()),
+                        ("body".to_string(), nullable_string),
+                        ("rank".to_string(), nullable_float),
+                    ]
+                )
+            ]
+        )
+    }
+
+    #[test]
+    fn fts5_auxiliary_functions() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE VIRTUAL TABLE docs USING fts5(title, body); CREATE TABLE foo (a);".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+
+        let shapes = analyze_query(
+            "SELECT highlight(d, 0, '<b>', '</b>') AS h, snippet(d, 1, '', '', '...', 8) AS s, bm25(d) AS score
+            FROM docs AS d WHERE d MATCH ?"
+                .to_string(),
+            schema.clone(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            shapes[0].relation.1,
+            vec![
+                ("h".to_string(), builtin_type(BuiltinType::String)),
+                ("s".to_string(), builtin_type(BuiltinType::String)),
+                ("score".to_string(), builtin_type(BuiltinType::Float)),
+            ]
+        );
+        assert_eq!(
+            shapes[0].params,
+            vec![Param {
+                index: 1,
+                name: None,
+                col_type: builtin_type(BuiltinType::String)
+            }]
+        );
+
+        // outside of the result columns too
+        for bad in [
+            "SELECT * FROM foo ORDER BY bm25(foo)",
+            "SELECT * FROM foo WHERE highlight(foo, 0, '<', '>') = ''",
+            "SELECT a FROM foo GROUP BY a HAVING snippet(docs, 0, '', '', '', 1) <> ''",
+        ] {
+            let shape = analyze_query(bad.to_string(), schema.clone(), &Options::default())
+                .unwrap()
+                .remove(0);
+            assert_eq!(shape.diagnostics.len(), 1, "{}", bad);
+            assert_eq!(shape.diagnostics[0].severity, Severity::Error);
+        }
+
+        for bad in [
+            "SELECT bm25(foo) AS score FROM foo",
+            "SELECT bm25(docs) AS score FROM foo",
+            "SELECT bm25() AS score FROM docs",
+        ] {
+            let shape = analyze_query(bad.to_string(), schema.clone(), &Options::default())
+                .unwrap()
+                .remove(0);
+            assert_eq!(shape.relation.1[0].1[0].0, TypeKind::Unresolved);
+            assert_eq!(shape.diagnostics[0].severity, Severity::Error);
+        }
+    }
+
+    #[test]
+    fn database_search_order() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "ATTACH 'analytics.db' AS analytics;
+            CREATE TABLE analytics.events (a TEXT NOT NULL);
+            CREATE TABLE foo (a INTEGER NOT NULL);
+            CREATE TEMP TABLE foo (a BLOB NOT NULL);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+
+        let query_shapes = get_result_shapes(
+            "SELECT a FROM foo;
+            SELECT main.foo.a FROM main.foo;
+            SELECT e.a FROM events AS e;
+            SELECT analytics.events.a FROM analytics.events"
+                .to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            query_shapes
+                .into_iter()
+                .map(|(_, cols)| cols[0].1.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                builtin_type(BuiltinType::Blob),
+                builtin_type(BuiltinType::Int),
+                builtin_type(BuiltinType::String),
+                builtin_type(BuiltinType::String),
+            ]
+        )
+    }
+
+    #[test]
+    fn identifiers_are_case_insensitive_and_unquoted() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE foo (id INTEGER NOT NULL, \"say \"\"hi\"\"\" TEXT NOT NULL);".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+
+        let query_shapes = get_result_shapes(
+            "SELECT ID, [Foo].Id, \"SAY \"\"HI\"\"\" FROM Foo; SELECT F.* FROM MAIN.FOO AS `f`"
+                .to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap();
+        let expected = vec![
+            ("id".to_string(), builtin_type(BuiltinType::Int)),
+            ("id".to_string(), builtin_type(BuiltinType::Int)),
+            ("say \"hi\"".to_string(), builtin_type(BuiltinType::String)),
+        ];
+        assert_eq!(
+            query_shapes,
+            vec![
+                (None, expected.clone()),
+                (None, vec![expected[0].clone(), expected[2].clone()])
+            ]
+        )
+    }
+
+    #[test]
+    fn ambiguous_columns() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE a (id INTEGER NOT NULL, x TEXT NOT NULL);
+            CREATE TABLE b (id INTEGER NOT NULL, y TEXT NOT NULL);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let shape =
+            |sql: &str| get_result_shapes(sql.to_string(), schema.clone(), &Options::default());
+
+        assert_eq!(
+            shape("SELECT id FROM a JOIN b ON a.id = b.id"),
+            Err(Error::Other(
+                "ambiguous column name: id. It could be any of a.id, b.id".to_string()
+            ))
+        );
+        assert_eq!(
+            shape("SELECT id FROM a AS l, b AS r"),
+            Err(Error::Other(
+                "ambiguous column name: id. It could be any of l.id, r.id".to_string()
+            ))
+        );
+
+        let id = vec![("id".to_string(), builtin_type(BuiltinType::Int))];
+        for sql in [
+            "SELECT id FROM a JOIN b USING (id)",
+            "SELECT id FROM a NATURAL JOIN b",
+            "SELECT a.id FROM a JOIN b ON a.id = b.id",
+            // the inner scope shadows the outer one
+            "SELECT (SELECT id FROM b) AS id FROM a",
+        ] {
+            assert_eq!(shape(sql), Ok(vec![(None, id.clone())]), "{}", sql);
+        }
+
+        assert_eq!(
+            shape("SELECT x FROM a JOIN b USING (x)"),
+            Err(Error::Other(
+                "cannot join using column x - column not present in both tables".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn unknown_tables_and_columns() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE users (id INTEGER NOT NULL, name TEXT NOT NULL);
+            CREATE TABLE user_roles (user_id INTEGER NOT NULL, role TEXT NOT NULL);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let error = |sql: &str| {
+            get_result_shapes(sql.to_string(), schema.clone(), &Options::default()).unwrap_err()
+        };
+
+        assert_eq!(
+            error("SELECT * FROM usres"),
+            Error::Other("no such table: usres. Did you mean users?".to_string())
+        );
+        assert_eq!(
+            error("SELECT * FROM main.user"),
+            Error::Other("no such table: main.user. Did you mean users?".to_string())
+        );
+        assert_eq!(
+            error("SELECT * FROM foo"),
+            Error::Other("no such table: foo".to_string())
+        );
+        assert_eq!(
+            error("SELECT nmae FROM users"),
+            Error::Other("no such column: nmae. Did you mean name?".to_string())
+        );
+        assert_eq!(
+            error("SELECT u.rol FROM users AS u JOIN user_roles AS r ON u.id = r.user_id"),
+            Error::Other("no such column: u.rol".to_string())
+        );
+        assert_eq!(
+            error("WITH admins AS (SELECT user_id FROM user_roles) SELECT * FROM admin"),
+            Error::Other("no such table: admin. Did you mean admins?".to_string())
+        );
+    }
+
+    #[test]
+    fn common_table_expressions() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE users (id INTEGER NOT NULL, name TEXT);".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+
+        let query_shapes = get_result_shapes(
+            "WITH named AS (SELECT id FROM users WHERE name IS NOT NULL), ids(x) AS (SELECT id FROM named)
+            SELECT x FROM ids;
+            WITH users AS (SELECT name AS id FROM main.users) SELECT id FROM users"
+                .to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            query_shapes,
+            vec![
+                (
+                    None,
+                    vec![("x".to_string(), builtin_type(BuiltinType::Int))]
+                ),
+                (
+                    None,
+                    vec![(
+                        "id".to_string(),
+                        vec![
+                            (TypeKind::Builtin, Some(BuiltinType::String), None),
+                            (TypeKind::Builtin, Some(BuiltinType::Null), None)
+                        ]
+                    )]
+                ),
+            ]
+        )
+    }
+
+    #[test]
+    fn using_and_natural_joins() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE a (id INTEGER NOT NULL, x TEXT NOT NULL);
+            CREATE TABLE b (id INTEGER, x TEXT NOT NULL, y TEXT NOT NULL);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let shape = |sql: &str| {
+            get_result_shapes(sql.to_string(), schema.clone(), &Options::default())
+                .unwrap()
+                .remove(0)
+                .1
+        };
+        let int = builtin_type(BuiltinType::Int);
+        let string = builtin_type(BuiltinType::String);
+        let nullable = |t: ColType| [t, builtin_type(BuiltinType::Null)].concat();
+
+        assert_eq!(
+            shape("SELECT * FROM a JOIN b USING (id)"),
+            vec![
+                ("id".to_string(), int.clone()),
+                ("x".to_string(), string.clone()),
+                ("x".to_string(), string.clone()),
+                ("y".to_string(), string.clone()),
+            ]
+        );
+        assert_eq!(
+            shape("SELECT * FROM a NATURAL JOIN b"),
+            vec![
+                ("id".to_string(), int.clone()),
+                ("x".to_string(), string.clone()),
+                ("y".to_string(), string.clone()),
+            ]
+        );
+        assert_eq!(
+            shape("SELECT b.*, id FROM a NATURAL LEFT JOIN b"),
+            vec![
+                ("id".to_string(), nullable(int.clone())),
+                ("x".to_string(), nullable(string.clone())),
+                ("y".to_string(), nullable(string.clone())),
+                ("id".to_string(), int.clone()),
+            ]
+        );
+        // the merged column is coalesce(a.x, b.x) which is only null when both are
+        assert_eq!(
+            shape("SELECT x, a.x AS ax, b.x AS bx, id FROM a FULL JOIN b USING (x, id)"),
+            vec![
+                ("x".to_string(), string.clone()),
+                ("ax".to_string(), nullable(string.clone())),
+                ("bx".to_string(), nullable(string.clone())),
+                ("id".to_string(), nullable(int.clone())),
+            ]
+        );
+        assert_eq!(
+            shape("SELECT * FROM b RIGHT JOIN a USING (id)"),
+            vec![
+                ("id".to_string(), int.clone()),
+                ("x".to_string(), nullable(string.clone())),
+                ("y".to_string(), nullable(string.clone())),
+                ("x".to_string(), string),
+            ]
+        );
+    }
+
+    #[test]
+    fn where_and_on_narrow_nullability() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE a (id INTEGER NOT NULL, x TEXT);
+            CREATE TABLE b (a_id INTEGER, y TEXT NOT NULL, z TEXT);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let shape = |sql: &str| {
+            get_result_shapes(sql.to_string(), schema.clone(), &Options::default())
+                .unwrap()
+                .remove(0)
+                .1
+                .into_iter()
+                .map(|(_, t)| t)
+                .collect::<Vec<_>>()
+        };
+        let string = builtin_type(BuiltinType::String);
+        let nullable = |t: ColType| [t, builtin_type(BuiltinType::Null)].concat();
+
+        assert_eq!(
+            shape("SELECT x, y, z FROM a LEFT JOIN b ON b.a_id = a.id"),
+            vec![
+                nullable(string.clone()),
+                nullable(string.clone()),
+                nullable(string.clone())
+            ]
+        );
+        // b.a_id = ... can't be true for the NULL row so the LEFT JOIN acts as an INNER JOIN
+        assert_eq!(
+            shape("SELECT x, y, z FROM a LEFT JOIN b ON b.a_id = a.id WHERE b.a_id = 1"),
+            vec![
+                nullable(string.clone()),
+                string.clone(),
+                nullable(string.clone())
+            ]
+        );
+        assert_eq!(
+            shape("SELECT x, y, z FROM a LEFT JOIN b ON b.a_id = a.id WHERE z IS NOT NULL AND (x IN ('p', 'q'))"),
+            vec![string.clone(), string.clone(), string.clone()]
+        );
+        assert_eq!(
+            shape("SELECT x, z FROM a JOIN b ON a.x = b.z"),
+            vec![string.clone(), string.clone()]
+        );
+        // neither OR nor IS nor a LEFT JOIN's ON clause rule out NULL
+        assert_eq!(
+            shape("SELECT x, z FROM a LEFT JOIN b ON b.z IS NOT NULL WHERE x IS NULL OR x = 'p' OR a.x IS 'q'"),
+            vec![nullable(string.clone()), nullable(string)]
+        );
+    }
+
+    #[test]
+    fn names_resolve_in_every_clause() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE a (id INTEGER NOT NULL, x TEXT);
+            CREATE TABLE b (a_id INTEGER, y TEXT NOT NULL);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let check = |sql: &str| {
+            get_result_shapes(sql.to_string(), schema.clone(), &Options::default()).map(|_| ())
+        };
+        let error = |message: &str| Err(Error::Other(message.to_string()));
+
+        for sql in [
+            "SELECT x AS name FROM a WHERE id = 1 AND \"not a column\" <> x ORDER BY name, 1 DESC",
+            "SELECT x FROM a WHERE EXISTS (SELECT 1 AS one FROM b WHERE b.a_id = a.id AND y = x)",
+            "SELECT x, count(id) AS n FROM a GROUP BY 1 HAVING n > 1",
+            "SELECT y FROM a JOIN b ON b.a_id = a.id WHERE a.rowid > 10 AND oid < 20",
+        ] {
+            assert_eq!(check(sql), Ok(()), "{}", sql);
+        }
+
+        assert_eq!(
+            check("SELECT x FROM a WHERE idd = 1"),
+            error("no such column: idd. Did you mean id?")
+        );
+        assert_eq!(
+            check("SELECT y FROM a JOIN b ON b.a_id = a.ix"),
+            error("no such column: a.ix. Did you mean id or x?")
+        );
+        assert_eq!(
+            check("SELECT y FROM a JOIN b ON b.a_id = c.id"),
+            error("no such column: c.id")
+        );
+        assert_eq!(
+            check("SELECT x FROM a GROUP BY x HAVING count(yy) > 1"),
+            error("no such column: yy")
+        );
+        assert_eq!(
+            check("SELECT x FROM a WHERE id IN (SELECT a_id FROM b WHERE z = 1)"),
+            error("no such column: z. Did you mean x or y?")
+        );
+        assert_eq!(
+            check("SELECT x, id FROM a ORDER BY x, 3"),
+            error("2nd ORDER BY term out of range - should be between 1 and 2")
+        );
+        assert_eq!(
+            check("SELECT x, lower(xx) AS lx FROM a"),
+            error("no such column: xx. Did you mean x?")
+        );
+    }
+
+    #[test]
+    fn cardinality() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE, org TEXT, handle TEXT);
+            CREATE UNIQUE INDEX users_org_handle ON users (org, handle);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let cardinalities = analyze_query(
+            "SELECT count(*) AS n FROM users WHERE org = ?;
+            SELECT org, count(*) AS n FROM users GROUP BY org;
+            SELECT * FROM users LIMIT 1;
+            SELECT * FROM users WHERE id = ?;
+            SELECT * FROM users AS u WHERE u.email = :email AND u.org IS NOT NULL;
+            SELECT * FROM users WHERE org = ? AND handle = ?;
+            SELECT * FROM users WHERE org = ?;
+            SELECT * FROM users WHERE id = id;
+            SELECT * FROM users WHERE rowid = 1 OR rowid = 2;
+            SELECT 1 AS one;
+            SELECT max(id) OVER () AS m FROM users;
+            INSERT INTO users (email) VALUES (?);
+            DELETE FROM users;"
+                .to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|shape| shape.cardinality)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            cardinalities,
+            vec![
+                Cardinality::One,
+                Cardinality::Many,
+                Cardinality::ZeroOrOne,
+                Cardinality::ZeroOrOne,
+                Cardinality::ZeroOrOne,
+                Cardinality::ZeroOrOne,
+                Cardinality::Many,
+                Cardinality::Many,
+                Cardinality::Many,
+                Cardinality::One,
+                Cardinality::Many,
+                Cardinality::Zero,
+                Cardinality::Zero,
+            ]
+        );
+    }
+
+    #[test]
+    fn statement_info() {
+        let schema = Schema::from_relations(
+            ddl::get_relation_shapes("CREATE TABLE t (a);".to_string(), &Options::default())
+                .unwrap(),
+        );
+        let statements = analyze_query(
+            "SELECT a FROM t;
+            INSERT INTO t VALUES (1);
+            DELETE FROM t RETURNING a;
+            CREATE INDEX t_a ON t (a);
+            PRAGMA table_info(t);
+            PRAGMA user_version = 2;
+            PRAGMA journal_mode = WAL;
+            PRAGMA user_version(5);
+            PRAGMA journal_mode(WAL);
+            BEGIN IMMEDIATE;
+            ATTACH 'other.db' AS other;
+            EXPLAIN QUERY PLAN SELECT a FROM t;
+            VACUUM;"
+                .to_string(),
+            schema.clone(),
+            &Options::default(),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|shape| {
+            let s = shape.statement;
+            (s.kind, s.read_only, s.returns_rows, s.needs_exclusive)
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                (StatementKind::Select, true, true, false),
+                (StatementKind::Insert, false, false, false),
+                (StatementKind::Delete, false, true, false),
+                (StatementKind::Ddl, false, false, false),
+                (StatementKind::Pragma, true, true, false),
+                (StatementKind::Pragma, false, false, false),
+                (StatementKind::Pragma, false, true, true),
+                (StatementKind::Pragma, false, false, false),
+                (StatementKind::Pragma, false, true, true),
+                (StatementKind::Transaction, true, false, false),
+                (StatementKind::Attach, true, false, false),
+                (StatementKind::Explain, true, true, false),
+                (StatementKind::Other, false, false, true),
+            ]
+        );
+
+        // only statements we can type come back from `get_result_shapes`
+        assert_eq!(
+            get_result_shapes(
+                "BEGIN; SELECT a FROM t; PRAGMA user_version; COMMIT;".to_string(),
+                schema,
+                &Options::default()
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn statement_spans() {
+        let schema = Schema::from_relations(
+            ddl::get_relation_shapes("CREATE TABLE t (a);".to_string(), &Options::default())
+                .unwrap(),
+        );
+        let sql = "SELECT a FROM t;\n\nDELETE FROM t /* all of it */;\nUPDATE t SET a = 1";
+        let shapes = analyze_query(sql.to_string(), schema, &Options::default()).unwrap();
+        let statements = shapes
+            .iter()
+            .map(|shape| {
+                let Span { start, end } = shape.span;
+                (
+                    &sql[start.offset..end.offset],
+                    start.line,
+                    shape.cardinality,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                ("SELECT a FROM t;", 1, Cardinality::Many),
+                ("DELETE FROM t /* all of it */;", 3, Cardinality::Zero),
+                ("UPDATE t SET a = 1", 4, Cardinality::Zero),
+            ]
+        );
+    }
+
+    #[test]
+    fn checking() {
+        let schema = Schema::from_relations(
+            ddl::get_relation_shapes(
+                "CREATE TABLE users (id INTEGER, name TEXT); CREATE TABLE posts (id INTEGER);"
+                    .to_string(),
+                &Options::default(),
+            )
+            .unwrap(),
+        );
+        let sql = "SELECT nmae FROM users;\nSELECT id FROM users, posts;\nSELECT 1;\nSELECT user.* FROM users;\nSELECT 'no such column' FROM users;\nSELEC 2";
+        let diagnostics = check_query(sql.to_string(), schema, &Options::default())
+            .into_iter()
+            .map(|d| {
+                let span = d.span.unwrap();
+                (
+                    d.code,
+                    &sql[span.start.offset..span.end.offset],
+                    span.start.line,
+                    d.fixes
+                        .into_iter()
+                        .map(|f| f.edits[0].text.clone())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (Code::NoSuchColumn, "nmae", 1, vec!["name".to_string()]),
+                (
+                    Code::AmbiguousColumn,
+                    "id",
+                    2,
+                    vec!["users.id".to_string(), "posts.id".to_string()]
+                ),
+                (Code::UnaliasedExpression, "1", 3, vec![]),
+                (Code::NoSuchTable, "user", 4, vec!["users".to_string()]),
+                (Code::UnaliasedExpression, "'no such column'", 5, vec![]),
+                (Code::Syntax, "SELEC", 6, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn checking_compounds() {
+        let schema = Schema::from_relations(
+            ddl::get_relation_shapes(
+                "CREATE TABLE t (id INTEGER, name TEXT);".to_string(),
+                &Options::default(),
+            )
+            .unwrap(),
+        );
+        let sql = "SELECT id FROM t UNION SELECT nmae FROM t;\n\
+            SELECT id FROM t UNION SELECT id FROM t WHERE nmae = 1;\n\
+            SELECT id FROM t UNION SELECT id FROM missing;";
+        let diagnostics = check_query(sql.to_string(), schema, &Options::default())
+            .into_iter()
+            .map(|d| (d.code, d.span.unwrap().start.line, d.message))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (
+                    Code::NoSuchColumn,
+                    1,
+                    "no such column: nmae. Did you mean name?".to_string()
+                ),
+                (
+                    Code::NoSuchColumn,
+                    2,
+                    "no such column: nmae. Did you mean name?".to_string()
+                ),
+                (Code::NoSuchTable, 3, "no such table: missing".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_results() {
+        let schema = Schema::from_relations(
+            ddl::get_relation_shapes(
+                "CREATE TABLE t (a INTEGER NOT NULL, b TEXT NOT NULL);".to_string(),
+                &Options::default(),
+            )
+            .unwrap(),
+        );
+        let shape = analyze_query(
+            "SELECT a, c, d + 1 AS e, b FROM t WHERE f = 1 ORDER BY 5".to_string(),
+            schema.clone(),
+            &Options::default(),
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(
+            shape.relation.1,
+            vec![
+                ("a".to_string(), builtin_type(BuiltinType::Int)),
+                (
+                    "c".to_string(),
+                    unresolved_type("no such column: c. Did you mean a or b?".to_string())
+                ),
+                ("e".to_string(), builtin_type(BuiltinType::Number)),
+                ("b".to_string(), builtin_type(BuiltinType::String)),
+            ]
+        );
+        assert_eq!(
+            shape
+                .diagnostics
+                .iter()
+                .map(|d| d.message.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "no such column: c. Did you mean a or b?",
+                "no such column: d. Did you mean a or b?",
+                "no such column: f. Did you mean a or b?",
+                "1st ORDER BY term out of range - should be between 1 and 4",
+            ]
+        );
+
+        // a problem in a FROM subquery or a CTE only leaves the columns read from it unresolved
+        // and one in a statement doesn't keep the others from being typed
+        let shapes = analyze_query(
+            "SELECT s.a, s.b FROM (SELECT a, c AS b FROM t) s;
+            WITH w AS (SELECT a, c FROM t) SELECT a FROM w;
+            SELECT a FROM missing;
+            SELECT b FROM t;
+            SELECT x.b FROM (SELECT b, b FROM t) x;"
+                .to_string(),
+            schema,
+            &Options::default(),
+        )
+        .unwrap();
+        let shapes = shapes
+            .into_iter()
+            .map(|shape| {
+                let messages = shape
+                    .diagnostics
+                    .into_iter()
+                    .map(|d| d.message)
+                    .collect::<Vec<_>>();
+                (shape.relation.1, messages)
+            })
+            .collect::<Vec<_>>();
+        let no_such_column = "no such column: c. Did you mean a or b?".to_string();
+        assert_eq!(
+            shapes,
+            vec![
+                (
+                    vec![
+                        ("a".to_string(), builtin_type(BuiltinType::Int)),
+                        ("b".to_string(), unresolved_type(no_such_column.clone())),
+                    ],
+                    vec![no_such_column.clone()]
+                ),
+                (
+                    vec![("a".to_string(), builtin_type(BuiltinType::Int))],
+                    vec![no_such_column]
+                ),
+                (vec![], vec!["no such table: missing".to_string()]),
+                (
+                    vec![("b".to_string(), builtin_type(BuiltinType::String))],
+                    vec![]
+                ),
+                // only the result's column names have to be unique
+                (
+                    vec![("b".to_string(), builtin_type(BuiltinType::String))],
+                    vec![]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unresolved_types() {
+        let schema = Schema::from_relations(
+            ddl::get_relation_shapes(
+                "CREATE TABLE t (a INTEGER NOT NULL, b TEXT);".to_string(),
+                &Options::default(),
+            )
+            .unwrap(),
+        );
+        let sql = "SELECT (a) AS a, b COLLATE NOCASE AS b, a BETWEEN 1 AND 2 AS c, frob(a) AS d, ifnull(b, '') AS e, ? AS f FROM t";
+        let shape = |options: &Options| {
+            analyze_query(sql.to_string(), schema.clone(), options)
+                .unwrap()
+                .remove(0)
+        };
+
+        let warned = shape(&Options::default());
+        assert_eq!(
+            warned.relation.1,
+            vec![
+                ("a".to_string(), builtin_type(BuiltinType::Int)),
+                (
+                    "b".to_string(),
+                    vec![
+                        (TypeKind::Builtin, Some(BuiltinType::String), None),
+                        (TypeKind::Builtin, Some(BuiltinType::Null), None)
+                    ]
+                ),
+                ("c".to_string(), builtin_type(BuiltinType::Boolean)),
+                (
+                    "d".to_string(),
+                    unresolved_type("unknown function frob()".to_string())
+                ),
+                (
+                    "e".to_string(),
+                    unresolved_type("the type of ifnull() depends on its arguments".to_string())
+                ),
+                (
+                    "f".to_string(),
+                    unresolved_type(
+                        "the type of parameter ? is whatever is bound to it".to_string()
+                    )
+                ),
+            ]
+        );
+        assert_eq!(
+            warned
+                .diagnostics
+                .iter()
+                .map(|d| (d.code, d.severity, d.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    Code::Unresolved,
+                    Severity::Warning,
+                    "can't type column d: unknown function frob()"
+                ),
+                (
+                    Code::Unresolved,
+                    Severity::Warning,
+                    "can't type column e: the type of ifnull() depends on its arguments"
+                ),
+                (
+                    Code::Unresolved,
+                    Severity::Warning,
+                    "can't type column f: the type of parameter ? is whatever is bound to it"
+                ),
+            ]
+        );
+        let span = warned.diagnostics[0].span.unwrap();
+        assert_eq!(&sql[span.start.offset..span.end.offset], "d");
+
+        let any = shape(&Options {
+            unresolved_types: UnresolvedTypes::Any,
+            ..Options::default()
+        });
+        assert_eq!(any.relation.1[3].1, builtin_type(BuiltinType::Any));
+        assert_eq!(any.diagnostics, vec![]);
+
+        let options = Options {
+            unresolved_types: UnresolvedTypes::Error,
+            ..Options::default()
+        };
+        assert_eq!(shape(&options).diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            get_result_shapes(sql.to_string(), schema.clone(), &options),
+            Err(Error::Other(
+                "can't type column d: unknown function frob()".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn array_mode() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE foo (a TEXT);".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let sql = "SELECT count(*), a, foo.a, a || 'x', lower(a), a IN ('p', 'q') FROM foo";
+
+        assert_eq!(
+            get_result_shapes(
+                "SELECT count(*) FROM foo".to_string(),
+                schema.clone(),
+                &Options::default()
+            ),
+            Err(Error::Other(
+                "Please alias (as) the expression used as a column (count(*)) or use array mode"
+                    .to_string()
+            ))
+        );
+        let names = get_result_shapes(
+            sql.to_string(),
+            schema,
+            &Options {
+                array_mode: true,
+                ..Options::default()
+            },
+        )
+        .unwrap()
+        .remove(0)
+        .1
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "count(*)",
+                "a",
+                "a",
+                "a || 'x'",
+                "lower(a)",
+                "a IN ('p', 'q')"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_columns() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE a (id INTEGER, b_id INTEGER); CREATE TABLE b (id INTEGER);".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let diagnostics = |sql: &str, options: &Options| {
+            analyze_query(sql.to_string(), schema.clone(), options)
+                .unwrap()
+                .remove(0)
+                .diagnostics
+        };
+
+        assert_eq!(
+            diagnostics(
+                "SELECT a.id, b.id FROM a JOIN b ON a.b_id = b.id",
+                &Options::default()
+            ),
+            vec![Diagnostic {
+                span: Some(spans::span("SELECT a.id, b.id FROM a JOIN b ON a.b_id = b.id", 9, 11)),
+                ..Diagnostic::new(
+                    Code::DuplicateColumn,
+                    Severity::Warning,
+                    "duplicate column name id from a, b. Only the last is kept when rows are read as objects. Alias them (AS) apart, e.g. a_id, b_id".to_string()
+                )
+                .about("id".to_string())
+            }]
+        );
+        assert_eq!(
+            diagnostics("SELECT *, 1 AS id FROM a", &Options::default()),
+            vec![Diagnostic {
+                span: Some(spans::span("SELECT *, 1 AS id FROM a", 15, 17)),
+                ..Diagnostic::new(
+                    Code::DuplicateColumn,
+                    Severity::Warning,
+                    "duplicate column name id from a, an expression. Only the last is kept when rows are read as objects. Alias them (AS) apart, e.g. id_1, id_2".to_string()
+                )
+                .about("id".to_string())
+            }]
+        );
+        assert_eq!(
+            diagnostics(
+                "SELECT id, b_id AS Id, (SELECT b.id, c.id FROM b, b AS c) AS x FROM a",
+                &Options::default()
+            ),
+            vec![]
+        );
+        assert_eq!(
+            diagnostics(
+                "SELECT a.id, b.id FROM a, b",
+                &Options {
+                    array_mode: true,
+                    ..Options::default()
+                }
+            ),
+            vec![]
+        );
+
+        let options = Options {
+            duplicate_columns: Severity::Error,
+            ..Options::default()
+        };
+        assert_eq!(
+            diagnostics("SELECT * FROM a, b", &options)[0].severity,
+            Severity::Error
+        );
+        assert_eq!(
+            get_result_shapes("SELECT * FROM a, b".to_string(), schema.clone(), &options),
+            Err(Error::Other("duplicate column name id from a, b. Only the last is kept when rows are read as objects. Alias them (AS) apart, e.g. a_id, b_id".to_string()))
+        );
+        assert!(get_result_shapes(
+            "SELECT * FROM a, b".to_string(),
+            schema,
+            &Options::default()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn column_origins() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE Users (id INTEGER PRIMARY KEY, name TEXT); CREATE TABLE posts (id INTEGER, user_id INTEGER);"
+                .to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let origins = |sql: &str| {
+            analyze_query(sql.to_string(), schema.clone(), &Options::default())
+                .unwrap()
+                .remove(0)
+                .origins
+        };
+        let origin = |table: &str, column: &str| {
+            Some(ColumnOrigin {
+                db: "main".to_string(),
+                table: table.to_string(),
+                column: column.to_string(),
+            })
+        };
+
+        assert_eq!(
+            origins("SELECT u.name AS author, p.*, upper(name) AS loud, (u.id) AS uid FROM users u JOIN posts p ON p.user_id = u.id"),
+            vec![
+                origin("Users", "name"),
+                origin("posts", "id"),
+                origin("posts", "user_id"),
+                None,
+                origin("Users", "id"),
+            ]
+        );
+        assert_eq!(
+            origins("WITH named AS (SELECT id AS uid, name FROM main.users) SELECT * FROM (SELECT uid, 1 AS one FROM named)"),
+            vec![origin("Users", "id"), None]
+        );
+        assert_eq!(
+            origins("SELECT id FROM users FULL JOIN posts USING (id)"),
+            vec![None]
+        );
+        assert_eq!(origins("INSERT INTO posts VALUES (1, 1)"), vec![]);
+    }
+
+    #[test]
+    fn dependencies() {
+        let schema = Schema::from_relations(
+            ddl::analyze_schema(
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+                CREATE TABLE Posts (id INTEGER PRIMARY KEY, user_id INTEGER, body TEXT);
+                CREATE TABLE audit (msg TEXT);
+                CREATE TABLE counts (user_id INTEGER, n INTEGER);
+                CREATE VIEW authors AS SELECT DISTINCT u.* FROM users u JOIN posts p ON p.user_id = u.id;
+                CREATE VIEW prolific AS SELECT * FROM authors WHERE id IN (SELECT user_id FROM counts);
+                CREATE TRIGGER log_edit AFTER UPDATE OF body ON posts BEGIN
+                    INSERT INTO audit VALUES ('edited ' || NEW.id);
+                END;
+                CREATE TRIGGER count_post AFTER INSERT ON posts BEGIN
+                    UPDATE counts SET n = n + 1 WHERE user_id = NEW.user_id;
+                    UPDATE posts SET body = trim(body) WHERE id = NEW.id;
+                END;"
+                    .to_string(),
+                &Options::default(),
+            )
+            .unwrap()
+            .relations,
+        );
+        let dependencies = |sql: &str, column_dependencies| {
+            get_dependencies(
+                sql.to_string(),
+                schema.clone(),
+                &Options {
+                    column_dependencies,
+                    ..Options::default()
+                },
+            )
+            .unwrap()
+        };
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let cols = |cols: &[(&str, &str)]| {
+            cols.iter()
+                .map(|(r, c)| (r.to_string(), c.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            dependencies(
+                "WITH recent AS (SELECT * FROM posts ORDER BY id DESC LIMIT 10)
+                SELECT name, (SELECT count(*) FROM audit) AS audits FROM users WHERE id IN (SELECT user_id FROM recent);
+                SELECT * FROM prolific;
+                UPDATE posts SET user_id = 1;
+                DELETE FROM posts WHERE id = ?;",
+                false
+            ),
+            vec![
+                Dependencies {
+                    reads: names(&["main.Posts", "main.users", "main.audit"]),
+                    ..Dependencies::default()
+                },
+                Dependencies {
+                    reads: names(&["main.prolific", "main.authors", "main.users", "main.Posts", "main.counts"]),
+                    ..Dependencies::default()
+                },
+                Dependencies {
+                    writes: names(&["main.Posts"]),
+                    ..Dependencies::default()
+                },
+                Dependencies {
+                    reads: names(&["main.Posts"]),
+                    writes: names(&["main.Posts"]),
+                    ..Dependencies::default()
+                },
+            ]
+        );
+
+        // the insert trigger updates posts which fires the update trigger
+        assert_eq!(
+            dependencies("INSERT INTO posts (user_id, body) VALUES (?, ?)", true),
+            vec![Dependencies {
+                reads: names(&["main.counts", "main.Posts"]),
+                writes: names(&["main.Posts", "main.counts", "main.audit"]),
+                read_columns: cols(&[
+                    ("main.counts", "n"),
+                    ("main.counts", "user_id"),
+                    ("main.Posts", "body"),
+                    ("main.Posts", "id"),
+                ]),
+                write_columns: cols(&[
+                    ("main.Posts", "user_id"),
+                    ("main.Posts", "body"),
+                    ("main.counts", "n"),
+                    ("main.audit", "msg"),
+                ]),
+                ..Dependencies::default()
+            }]
+        );
+
+        assert_eq!(
+            dependencies(
+                "SELECT p.body, name FROM posts p JOIN users USING (id) WHERE p.user_id = ?",
+                true
+            ),
+            vec![Dependencies {
+                reads: names(&["main.Posts", "main.users"]),
+                writes: vec![],
+                read_columns: cols(&[
+                    ("main.Posts", "id"),
+                    ("main.users", "id"),
+                    ("main.Posts", "body"),
+                    ("main.users", "name"),
+                    ("main.Posts", "user_id"),
+                ]),
+                write_columns: vec![],
+                ..Dependencies::default()
+            }]
+        );
+
+        // SQL recorded by an older version, or edited by hand, may not parse
+        let broken = Schema::from_relations(vec![
+            (
+                "main.t".to_string(),
+                vec![("a".to_string(), builtin_type(BuiltinType::Int))],
+                RelationInfo {
+                    triggers: vec!["CREATE TRIGGER t_insert AFTER INSERT ON t BEGIN".to_string()],
+                    ..RelationInfo::default()
+                },
+            ),
+            (
+                "main.v".to_string(),
+                vec![("a".to_string(), builtin_type(BuiltinType::Int))],
+                RelationInfo {
+                    view: Some("CREATE TABLE v (a INTEGER)".to_string()),
+                    ..RelationInfo::default()
+                },
+            ),
+        ]);
+        let messages = get_dependencies(
+            "INSERT INTO t SELECT a FROM v".to_string(),
+            broken,
+            &Options::default(),
+        )
+        .unwrap()
+        .remove(0)
+        .diagnostics
+        .into_iter()
+        .map(|d| (d.code, d.severity, d.message))
+        .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            (
+                Code::Syntax,
+                Severity::Warning,
+                "can't follow view main.v: its SQL is not a CREATE VIEW statement".to_string()
+            )
+        );
+        assert!(messages[1]
+            .2
+            .starts_with("can't follow a trigger on main.t: "));
+    }
+
+    #[test]
+    fn sqlite_column_names() {
+        let schema_shapes = ddl::get_relation_shapes(
+            "CREATE TABLE foo (a INTEGER);".to_string(),
+            &Options::default(),
+        )
+        .unwrap();
+        let schema = Schema::from_relations(schema_shapes);
+        let sql = "SELECT a+1, a + 1 FROM foo;\nSELECT (SELECT max(a) FROM foo),\n  COUNT( * ), \"a\", 'x' AS y FROM foo WHERE a > 1";
+        let names = get_result_shapes(
+            sql.to_string(),
+            schema,
+            &Options {
+                sqlite_column_names: true,
+                ..Options::default()
+            },
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(_, cols)| cols.into_iter().map(|(name, _)| name).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                vec!["a+1", "a + 1"],
+                vec!["(SELECT max(a) FROM foo)", "COUNT( * )", "a", "y"],
+            ]
+        );
+    }
+
     // test aliases
     // test join without join (comma join)
     // it'd probably be easier to test this stuff fomr TypeScript. Much less verbose.
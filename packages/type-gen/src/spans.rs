@@ -0,0 +1,232 @@
+use sqlite3_parser::lexer::{
+    sql::{TokenType, Tokenizer},
+    Scanner,
+};
+
+use crate::types::{Position, Span};
+use crate::util;
+
+// The byte offset of a position the parser reports. Lines and columns start at 1 and columns count bytes.
+pub fn offset_of(sql: &str, line: u64, column: usize) -> usize {
+    let line_start = match line {
+        0 | 1 => 0,
+        line => sql
+            .match_indices('\n')
+            .nth(line as usize - 2)
+            .map_or(sql.len(), |(i, _)| i + 1),
+    };
+    (line_start + column.saturating_sub(1)).min(sql.len())
+}
+
+// Where the statement between the byte offsets `from` and `to` is, leaving out the whitespace and comments around it.
+// `to` is where the parser left off, just past the statement's `;` if it has one.
+pub fn statement_span(sql: &str, from: usize, to: usize) -> Span {
+    let mut scanner = Scanner::new(Tokenizer::new());
+    let mut first = None;
+    let mut last = from;
+    let text = &sql[from..to];
+    while let Ok((start, Some(_), end)) = scanner.scan(text.as_bytes()) {
+        first.get_or_insert(from + start);
+        last = from + end;
+    }
+    span(sql, first.unwrap_or(from), last)
+}
+
+pub fn span(sql: &str, start: usize, end: usize) -> Span {
+    Span {
+        start: position(sql, start),
+        end: position(sql, end),
+    }
+}
+
+// The first run of tokens between `from` and `to` that spells `needle`, as byte offsets of its start,
+// of its last token and of its end. Identifiers match however they're quoted and cased, like SQLite matches them.
+pub fn find_tokens(
+    sql: &str,
+    from: usize,
+    to: usize,
+    needle: &str,
+) -> Option<(usize, usize, usize)> {
+    let needle = tokens(needle)?;
+    let haystack = tokens(&sql[from..to])?;
+    if needle.is_empty() {
+        return None;
+    }
+    haystack
+        .windows(needle.len())
+        .find(|window| {
+            window
+                .iter()
+                .zip(&needle)
+                .all(|((ty, text, _, _), (n_ty, n_text, _, _))| {
+                    if *ty == TokenType::TK_ID || *n_ty == TokenType::TK_ID {
+                        util::ident_eq(&util::unquote_ident(text), &util::unquote_ident(n_text))
+                    } else {
+                        ty == n_ty && text.eq_ignore_ascii_case(n_text)
+                    }
+                })
+        })
+        .map(|window| {
+            let (first, last) = (&window[0], &window[window.len() - 1]);
+            (from + first.2, from + last.2, from + last.3)
+        })
+}
+
+fn tokens(sql: &str) -> Option<Vec<(TokenType, &str, usize, usize)>> {
+    let mut scanner = Scanner::new(Tokenizer::new());
+    let mut ret = vec![];
+    loop {
+        match scanner.scan(sql.as_bytes()) {
+            Ok((start, Some((_, ty)), end)) => ret.push((ty, &sql[start..end], start, end)),
+            Ok((_, None, _)) => return Some(ret),
+            Err(_) => return None,
+        }
+    }
+}
+
+// Lines and columns start at 1. Columns count characters.
+fn position(sql: &str, offset: usize) -> Position {
+    let before = &sql[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        offset,
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+// The source text of each result column of the statement's outermost select, as written.
+// SQLite names an unaliased expression column after this text. E.g., `count(*)` or `a+1`.
+// Empty when the statement isn't a select or can't be tokenized.
+pub fn result_column_texts(stmt_sql: &str) -> Vec<String> {
+    let mut scanner = Scanner::new(Tokenizer::new());
+    let mut tokens = vec![];
+    loop {
+        match scanner.scan(stmt_sql.as_bytes()) {
+            Ok((start, Some((_, ty)), end)) => tokens.push((ty, start, end)),
+            Ok((_, None, _)) => break,
+            Err(_) => return vec![],
+        }
+    }
+
+    // The outermost select is the first at depth 0. Those of a WITH clause are in parentheses.
+    let mut depth = 0;
+    let mut tokens = tokens.into_iter().skip_while(|(ty, _, _)| {
+        match ty {
+            TokenType::TK_LP => depth += 1,
+            TokenType::TK_RP => depth -= 1,
+            _ => {}
+        }
+        !(depth == 0 && *ty == TokenType::TK_SELECT)
+    });
+    if tokens.next().is_none() {
+        return vec![];
+    }
+
+    let mut ret = vec![];
+    let mut column: Option<(usize, usize)> = None;
+    let mut depth = 0;
+    for (ty, start, end) in tokens {
+        match ty {
+            TokenType::TK_DISTINCT | TokenType::TK_ALL if column.is_none() && ret.is_empty() => {
+                continue
+            }
+            TokenType::TK_LP => depth += 1,
+            TokenType::TK_RP => depth -= 1,
+            TokenType::TK_COMMA if depth == 0 => {
+                ret.extend(column.take().map(|(s, e)| stmt_sql[s..e].to_string()));
+                continue;
+            }
+            TokenType::TK_FROM
+            | TokenType::TK_WHERE
+            | TokenType::TK_GROUP
+            | TokenType::TK_HAVING
+            | TokenType::TK_WINDOW
+            | TokenType::TK_ORDER
+            | TokenType::TK_LIMIT
+            | TokenType::TK_UNION
+            | TokenType::TK_EXCEPT
+            | TokenType::TK_INTERSECT
+            | TokenType::TK_SEMI
+                if depth == 0 =>
+            {
+                break
+            }
+            _ => {}
+        }
+        column = Some(column.map_or((start, end), |(s, _)| (s, end)));
+    }
+    ret.extend(column.map(|(s, e)| stmt_sql[s..e].to_string()));
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets() {
+        let sql = "SELECT 1;\nSELECT\n 2;";
+        assert_eq!(offset_of(sql, 1, 10), 9);
+        assert_eq!(offset_of(sql, 3, 4), 20);
+    }
+
+    #[test]
+    fn statement_spans() {
+        let sql = "SELECT 1;\n  -- the next one\n  SELECT 'é', 2 ;  \nSELECT 3";
+        let first = sql.find(';').unwrap() + 1;
+        let second = sql.rfind(';').unwrap() + 1;
+        assert_eq!(
+            statement_span(sql, first, second),
+            Span {
+                start: Position {
+                    offset: 30,
+                    line: 3,
+                    column: 3
+                },
+                end: Position {
+                    offset: 46,
+                    line: 3,
+                    column: 18
+                },
+            }
+        );
+        assert_eq!(
+            statement_span(sql, second, sql.len()).start,
+            Position {
+                offset: 49,
+                line: 4,
+                column: 1
+            }
+        );
+    }
+
+    #[test]
+    fn finding_tokens() {
+        let sql = "SELECT 1; SELECT u.\"Name\" FROM main . users u";
+        assert_eq!(find_tokens(sql, 9, sql.len(), "u.name"), Some((17, 19, 25)));
+        assert_eq!(
+            find_tokens(sql, 9, sql.len(), "MAIN.users"),
+            Some((31, 38, 43))
+        );
+        assert_eq!(find_tokens(sql, 0, 9, "u"), None);
+    }
+
+    #[test]
+    fn result_columns() {
+        assert_eq!(
+            result_column_texts(
+                "WITH x AS (SELECT 1 AS y) SELECT DISTINCT count(*),a+1, f(b, c)  AS z, * FROM x"
+            ),
+            vec!["count(*)", "a+1", "f(b, c)  AS z", "*"]
+        );
+        assert_eq!(
+            result_column_texts("SELECT a /* the a */ FROM t UNION SELECT b FROM u"),
+            vec!["a"]
+        );
+        assert_eq!(
+            result_column_texts("INSERT INTO t VALUES (1)"),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use sqlite3_parser::ast::{Expr, Id, Name, OneSelect, Select, Stmt, TriggerCmd, TriggerEvent};
+
+use crate::diagnostics::{Code, Diagnostic};
+use crate::error::Error;
+use crate::options::Options;
+use crate::queries::{self, Context, FromRelation};
+use crate::types::*;
+use crate::util;
+use crate::walk::walk_expr;
+
+// Type-check the WHEN clause and body of a CREATE TRIGGER against the schema.
+// SQLite only resolves these when the trigger fires so a typo would otherwise show up at runtime.
+// `NEW` and `OLD` are in scope with the columns of the triggering table, depending on the event:
+// https://www.sqlite.org/lang_createtrigger.html
+pub fn check_trigger(stmt: &Stmt, schema: &Schema, options: &Options) -> Vec<Diagnostic> {
+    let (temporary, trigger_name, event, tbl_name, when_clause, commands) = match stmt {
+        Stmt::CreateTrigger {
+            temporary,
+            trigger_name,
+            event,
+            tbl_name,
+            when_clause,
+            commands,
+            ..
+        } => (
+            *temporary,
+            trigger_name,
+            event,
+            tbl_name,
+            when_clause,
+            commands,
+        ),
+        _ => return vec![],
+    };
+    // A trigger lives in the database of its table and its body can only see that database.
+    // TEMP triggers are the exception and can be put on and see tables of any database.
+    // https://www.sqlite.org/lang_createtrigger.html#temp_triggers_on_non_temp_tables
+    let table_db = tbl_name
+        .db_name
+        .as_ref()
+        .or(trigger_name.db_name.as_ref())
+        .map(|n| util::unquote_ident(&n.0));
+    // what the body's selects resolve tables against
+    let body_schema;
+    let mut checker = Checker {
+        trigger_name: util::unquote_ident(&trigger_name.name.0).to_string(),
+        event_name: match event {
+            TriggerEvent::Insert => "INSERT",
+            TriggerEvent::Update | TriggerEvent::UpdateOf(_) => "UPDATE",
+            TriggerEvent::Delete => "DELETE",
+        },
+        body_db: None,
+        ctx: Context {
+            schema,
+            options,
+            ctes: &[],
+            column_texts: &[],
+            diagnostics: None,
+            outermost: false,
+        },
+        diagnostics: vec![],
+    };
+
+    let table_name = util::unquote_ident(&tbl_name.name.0);
+    let lookup_db = if temporary {
+        table_db.as_deref()
+    } else {
+        Some(table_db.as_deref().unwrap_or("main"))
+    };
+    let (db, table_cols) = match schema.get(lookup_db, &table_name) {
+        Some((db, (cols, _))) => (db, cols),
+        None => {
+            // like a select's, the message names a database only when the SQL does
+            checker.report(Error::no_such_table(
+                table_db.as_deref(),
+                &table_name,
+                schema.relation_names(lookup_db),
+            ));
+            return checker.diagnostics;
+        }
+    };
+    if !temporary {
+        checker.body_db = Some(db.to_string());
+        body_schema = schema.database(db);
+        checker.ctx.schema = &body_schema;
+    }
+    let table_name = format!("{}.{}", db, table_name);
+    if let TriggerEvent::UpdateOf(col_names) = event {
+        checker.check_col_names(&table_name, table_cols, col_names);
+    }
+
+    let pseudo_names: &[&str] = match event {
+        TriggerEvent::Insert => &["NEW"],
+        TriggerEvent::Update | TriggerEvent::UpdateOf(_) => &["NEW", "OLD"],
+        TriggerEvent::Delete => &["OLD"],
+    };
+    let pseudo_relations = pseudo_names
+        .iter()
+        .map(|name| FromRelation {
+            db: None,
+            name: Some(name.to_string()),
+            cols: table_cols.to_vec(),
+            ..FromRelation::default()
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(when) = when_clause {
+        checker.check_expr(when, &pseudo_relations);
+    }
+    for command in commands {
+        checker.check_command(command, &pseudo_relations);
+    }
+
+    checker.diagnostics
+}
+
+struct Checker<'a> {
+    trigger_name: String,
+    event_name: &'static str,
+    // Where unqualified table names in the body are looked up. Everywhere when None.
+    body_db: Option<String>,
+    ctx: Context<'a>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, error: Error) {
+        let context = format!("in trigger {}", self.trigger_name);
+        self.diagnostics
+            .push(Diagnostic::from(error).within(&context));
+    }
+
+    fn check_command(&mut self, command: &TriggerCmd, pseudo_relations: &[FromRelation]) {
+        match command {
+            TriggerCmd::Insert {
+                tbl_name,
+                col_names,
+                select,
+                ..
+            } => {
+                let Some(target) = self.target(tbl_name) else {
+                    return;
+                };
+                let expected = if let Some(col_names) = col_names {
+                    self.check_col_names(&relation_name(&target), &target.cols, col_names);
+                    col_names.len()
+                } else {
+                    target.cols.len()
+                };
+                match &select.body.select {
+                    OneSelect::Values(rows) if select.body.compounds.is_none() => {
+                        for row in rows {
+                            if row.len() != expected {
+                                self.report(column_count(row.len(), expected));
+                            }
+                            for e in row {
+                                self.check_expr(e, pseudo_relations);
+                            }
+                        }
+                    }
+                    _ => {
+                        if let Some(relation) = self.check_select(select, pseudo_relations) {
+                            if relation.1.len() != expected {
+                                self.report(column_count(relation.1.len(), expected));
+                            }
+                        }
+                    }
+                }
+            }
+            TriggerCmd::Update {
+                tbl_name,
+                sets,
+                where_clause,
+                ..
+            } => {
+                let Some(target) = self.target(tbl_name) else {
+                    return;
+                };
+                for set in sets {
+                    self.check_col_names(&relation_name(&target), &target.cols, &set.col_names);
+                }
+                let mut scope = vec![target];
+                scope.extend(pseudo_relations.iter().cloned());
+                for set in sets {
+                    self.check_expr(&set.expr, &scope);
+                }
+                if let Some(where_clause) = where_clause {
+                    self.check_expr(where_clause, &scope);
+                }
+            }
+            TriggerCmd::Delete {
+                tbl_name,
+                where_clause,
+            } => {
+                let Some(target) = self.target(tbl_name) else {
+                    return;
+                };
+                let mut scope = vec![target];
+                scope.extend(pseudo_relations.iter().cloned());
+                if let Some(where_clause) = where_clause {
+                    self.check_expr(where_clause, &scope);
+                }
+            }
+            TriggerCmd::Select(select) => {
+                self.check_select(select, pseudo_relations);
+            }
+        }
+    }
+
+    fn target(&mut self, tbl_name: &Name) -> Option<FromRelation> {
+        let name = util::unquote_ident(&tbl_name.0);
+        match self.ctx.schema.get(self.body_db.as_deref(), &name) {
+            Some((db, (cols, info))) => Some(FromRelation {
+                db: Some(db.to_string()),
+                name: Some(name.to_string()),
+                cols: cols.to_vec(),
+                info: info.clone(),
+                ..FromRelation::default()
+            }),
+            None => {
+                let candidates = self.ctx.schema.relation_names(self.body_db.as_deref());
+                self.report(Error::no_such_table(None, &name, candidates));
+                None
+            }
+        }
+    }
+
+    fn check_col_names(&mut self, relation_name: &str, cols: &[Col], col_names: &[Name]) {
+        for Name(col_name) in col_names {
+            let col_name = util::unquote_ident(col_name);
+            if !cols.iter().any(|c| util::ident_eq(&c.0, &col_name)) {
+                self.report(Error::no_column_named(
+                    relation_name,
+                    &col_name,
+                    cols.iter().map(|c| c.0.as_str()),
+                ));
+            }
+        }
+    }
+
+    // The relation the select returns, when it could be typed
+    fn check_select(&mut self, select: &Select, scope: &[FromRelation]) -> Option<Relation> {
+        match queries::select_to_relation(select, scope, &self.ctx) {
+            Ok(relation) => Some(relation),
+            Err(e) => {
+                self.report(e);
+                None
+            }
+        }
+    }
+
+    fn check_expr(&mut self, expr: &Expr, scope: &[FromRelation]) {
+        let mut problems = vec![];
+        let mut subqueries = vec![];
+        walk_expr(expr, &mut |e| match e {
+            Expr::Qualified(Name(qualifier), Name(col_name)) => {
+                let qualifier = util::unquote_ident(qualifier);
+                let col_name = util::unquote_ident(col_name);
+                match scope.iter().find(|r| r.is_named(None, &qualifier)) {
+                    Some(relation) => {
+                        if relation.col(&col_name).is_none() {
+                            problems.push(Error::no_such_column(
+                                Some(&qualifier),
+                                &col_name,
+                                relation.cols.iter().map(|c| c.0.as_str()),
+                            ));
+                        }
+                    }
+                    None if is_pseudo_relation(&qualifier) => problems.push(Error::Other(format!(
+                        "{} is not available in {} triggers",
+                        qualifier.to_uppercase(),
+                        self.event_name
+                    ))),
+                    None => problems.push(Error::no_such_column(Some(&qualifier), &col_name, [])),
+                }
+            }
+            // Quoted identifiers that don't name a column are treated as strings by SQLite
+            Expr::Id(Id(name)) if !name.starts_with(['"', '`', '[']) => {
+                // NEW and OLD columns must always be qualified. They're the only relations without a database.
+                let mut tables = scope.iter().filter(|r| r.db.is_some());
+                let found = tables.clone().any(|r| r.col(name).is_some());
+                if !found && !util::ident_eq(name, "true") && !util::ident_eq(name, "false") {
+                    problems.push(Error::no_such_column(
+                        None,
+                        name,
+                        tables
+                            .by_ref()
+                            .flat_map(|r| r.cols.iter().map(|c| c.0.as_str())),
+                    ));
+                }
+            }
+            Expr::Subquery(select) | Expr::Exists(select) | Expr::InSelect { rhs: select, .. } => {
+                subqueries.push(select.as_ref())
+            }
+            _ => {}
+        });
+        for problem in problems {
+            self.report(problem);
+        }
+        for select in subqueries {
+            self.check_select(select, scope);
+        }
+    }
+}
+
+// An INSERT giving a different number of values than it names columns
+fn column_count(values: usize, columns: usize) -> Error {
+    Error::coded(
+        Code::ColumnCount,
+        format!("{} values for {} columns", values, columns),
+    )
+}
+
+fn is_pseudo_relation(name: &str) -> bool {
+    util::ident_eq(name, "new") || util::ident_eq(name, "old")
+}
+
+fn relation_name(relation: &FromRelation) -> String {
+    format!(
+        "{}.{}",
+        relation.db.as_deref().unwrap_or("main"),
+        relation.name.as_deref().unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ddl;
+    use crate::options::Options;
+
+    fn messages(ddl: &str) -> Vec<String> {
+        ddl::analyze_schema(ddl.to_string(), &Options::default())
+            .unwrap()
+            .diagnostics
+            .into_iter()
+            .map(|d| d.message)
+            .collect()
+    }
+
+    const TABLES: &str =
+        "CREATE TABLE issue (id INTEGER PRIMARY KEY, owner_id INTEGER, title TEXT);
+        CREATE TABLE log (issue_id INTEGER, msg TEXT);";
+
+    #[test]
+    fn valid_trigger_bodies() {
+        assert_eq!(
+            messages(&format!(
+                "{}
+                CREATE TRIGGER t1 AFTER UPDATE OF title ON issue WHEN NEW.title <> OLD.title BEGIN
+                    INSERT INTO log (issue_id, msg) VALUES (NEW.id, OLD.title || ' -> ' || NEW.title);
+                    UPDATE log SET msg = NEW.title WHERE issue_id = OLD.id;
+                    DELETE FROM log WHERE issue_id = OLD.id AND msg IS NULL;
+                    SELECT NEW.owner_id;
+                END;",
+                TABLES
+            )),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn unknown_columns_and_tables() {
+        assert_eq!(
+            messages(&format!(
+                "{}
+                CREATE TRIGGER t2 AFTER INSERT ON issue BEGIN
+                    INSERT INTO log (issue_id, mesage) VALUES (NEW.ownr_id, 'created');
+                    DELETE FROM logs WHERE issue_id = NEW.id;
+                    UPDATE log SET msg = 'x' WHERE issu_id = NEW.id;
+                END;
+                CREATE TRIGGER t6 AFTER INSERT ON issues BEGIN
+                    SELECT 1;
+                END;",
+                TABLES
+            )),
+            vec![
+                "in trigger t2: table main.log has no column named mesage",
+                "in trigger t2: no such column: NEW.ownr_id. Did you mean owner_id?",
+                "in trigger t2: no such table: logs. Did you mean log?",
+                "in trigger t2: no such column: issu_id. Did you mean issue_id?",
+                "in trigger t6: no such table: issues. Did you mean issue?",
+            ]
+        );
+    }
+
+    #[test]
+    fn new_and_old_depend_on_the_event() {
+        assert_eq!(
+            messages(&format!(
+                "{}
+                CREATE TRIGGER t3 AFTER INSERT ON issue BEGIN
+                    INSERT INTO log VALUES (OLD.id, 'x');
+                END;
+                CREATE TRIGGER t4 BEFORE DELETE ON issue BEGIN
+                    INSERT INTO log VALUES (NEW.id, 'x', 'y');
+                END;",
+                TABLES
+            )),
+            vec![
+                "in trigger t3: OLD is not available in INSERT triggers",
+                "in trigger t4: 3 values for 2 columns",
+                "in trigger t4: NEW is not available in DELETE triggers",
+            ]
+        );
+    }
+
+    #[test]
+    fn selects_see_only_the_trigger_database() {
+        assert_eq!(
+            messages(&format!(
+                "{}
+                ATTACH 'archive.db' AS archive;
+                CREATE TABLE archive.old_issue (id INTEGER);
+                CREATE TRIGGER t5 AFTER INSERT ON issue BEGIN
+                    INSERT INTO log SELECT id, title FROM old_issue;
+                    INSERT INTO log SELECT id, title, owner_id FROM issue;
+                    SELECT (SELECT count(*) FROM old_issue);
+                END;",
+                TABLES
+            )),
+            vec![
+                "in trigger t5: no such table: old_issue",
+                "in trigger t5: 3 values for 2 columns",
+                "in trigger t5: no such table: old_issue",
+            ]
+        );
+    }
+}
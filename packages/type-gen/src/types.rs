@@ -1,4 +1,9 @@
+use crate::diagnostics::Diagnostic;
+use crate::options::Options;
+use crate::util;
 use serde::{Deserialize, Serialize};
+use sqlite3_parser::ast::{Literal, Type, TypeSize};
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum BuiltinType {
@@ -34,10 +39,466 @@ pub type ColName = String;
 pub type ColType = Vec<(TypeKind, Option<BuiltinType>, Option<String>)>;
 pub type Col = (ColName, ColType);
 pub type Relation = (Option<RelationName>, Vec<Col>);
-pub type NamedRelation = (RelationName, Vec<Col>);
+pub type NamedRelation = (RelationName, Vec<Col>, RelationInfo);
+
+// What queries are checked against. Relations by `db.name` along with what we know about them.
+// Lookups are case-insensitive like SQLite's. The columns keep their declared spelling.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    // Keyed by `ident_key` of the name. The declared spelling of the name is kept for messages.
+    relations: HashMap<String, (RelationName, (Vec<Col>, RelationInfo))>,
+    // Databases other than `main` and `temp` in the order their relations were first seen.
+    // That is attach order for any schema `get_relation_shapes` returned.
+    attached: Vec<String>,
+}
+
+impl Schema {
+    pub fn from_relations(relations: Vec<NamedRelation>) -> Self {
+        let mut ret = Schema::default();
+        for (name, cols, info) in relations {
+            ret.insert(name, cols, info);
+        }
+        ret
+    }
+
+    pub fn insert(&mut self, name: RelationName, cols: Vec<Col>, info: RelationInfo) {
+        let (db, _) = split_relation_name(&name);
+        if !["main", "temp"]
+            .into_iter()
+            .chain(self.attached.iter().map(|a| a.as_str()))
+            .any(|a| util::ident_eq(a, db))
+        {
+            self.attached.push(db.to_string());
+        }
+        self.relations
+            .insert(util::ident_key(&name), (name, (cols, info)));
+    }
+
+    // Only the relations of database `db`.
+    pub fn database(&self, db: &str) -> Schema {
+        Schema::from_relations(
+            self.relations
+                .values()
+                .filter(|(name, _)| util::ident_eq(split_relation_name(name).0, db))
+                .map(|(name, (cols, info))| (name.clone(), cols.clone(), info.clone()))
+                .collect(),
+        )
+    }
+
+    pub fn extend(&mut self, other: Schema) {
+        for (_, (name, (cols, info))) in other.relations {
+            self.insert(name, cols, info);
+        }
+    }
+
+    // Find a relation the way SQLite does. A name qualified with a database only looks in that database.
+    // An unqualified name searches temp, main and then attached databases.
+    // https://www.sqlite.org/lang_naming.html
+    // Returns the name of the database the relation was found in along with the relation.
+    pub fn get<'a>(
+        &'a self,
+        db: Option<&'a str>,
+        name: &str,
+    ) -> Option<(&'a str, &'a (Vec<Col>, RelationInfo))> {
+        match db {
+            Some(db) => self
+                .relations
+                .get(&util::ident_key(&format!("{}.{}", db, name)))
+                .map(|(_, r)| (db, r)),
+            None => ["temp", "main"]
+                .into_iter()
+                .chain(self.attached.iter().map(|a| a.as_str()))
+                .find_map(|db| {
+                    self.relations
+                        .get(&util::ident_key(&format!("{}.{}", db, name)))
+                        .map(|(_, r)| (db, r))
+                }),
+        }
+    }
+
+    // The database and table name of a relation `get` found, spelled as declared
+    pub fn declared_name(&self, db: &str, name: &str) -> Option<(&str, &str)> {
+        self.relations
+            .get(&util::ident_key(&format!("{}.{}", db, name)))
+            .map(|(name, _)| split_relation_name(name))
+    }
+
+    // The names of the relations `get` could find in `db`, or in any database when None.
+    // Unqualified since that's how they'd be written in that case.
+    pub fn relation_names(&self, db: Option<&str>) -> Vec<&str> {
+        self.relations
+            .values()
+            .map(|(name, _)| split_relation_name(name))
+            .filter(|(relation_db, _)| db.map_or(true, |db| util::ident_eq(db, relation_db)))
+            .map(|(_, name)| name)
+            .collect()
+    }
+}
+
+// `main.foo` -> (`main`, `foo`)
+pub fn split_relation_name(name: &str) -> (&str, &str) {
+    name.split_once('.').unwrap_or(("main", name))
+}
+
+// Facts about a relation that aren't captured by its columns.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RelationInfo {
+    pub strict: bool,
+    pub without_rowid: bool,
+    // The module backing a virtual table, lowercased. E.g., `fts5`.
+    pub module: Option<String>,
+    // Columns that can be selected by name but are left out of `*`. E.g., FTS5's `rank`.
+    pub hidden_columns: Vec<ColName>,
+    // Sets of columns no two rows have the same values for.
+    // The primary key, UNIQUE constraints and unique indexes that aren't partial or on expressions.
+    pub unique_keys: Vec<Vec<ColName>>,
+    // The CREATE VIEW statement of a view. None for tables.
+    pub view: Option<String>,
+    // The CREATE TRIGGER statements of the triggers on the relation, in the order they were created.
+    pub triggers: Vec<String>,
+    // The type name each column was declared with, in column order. None for a column declared without one.
+    pub declared_types: Vec<Option<String>>,
+}
+
+// The relations a schema defines along with any problems found in it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaShape {
+    pub relations: Vec<NamedRelation>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// A result shape along with everything a runtime needs to consume it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultShape {
+    pub relation: Relation,
+    pub converters: Vec<Converter>,
+    pub params: Vec<Param>,
+    pub cardinality: Cardinality,
+    pub diagnostics: Vec<Diagnostic>,
+    // Where each column of `relation` reads its values from. None when it's computed.
+    pub origins: Vec<Option<ColumnOrigin>>,
+    pub statement: StatementInfo,
+    // Where the statement is in the query, for lining shapes up with the source
+    pub span: Span,
+}
+
+// A range of the source text. `end` is exclusive.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+// `offset` is in bytes. `line` and `column` start at 1 and columns count characters.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+// What kind of statement a shape is for and what running it involves.
+// Lets a driver pick between running a statement and fetching its rows, and between read and write connections.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatementInfo {
+    pub kind: StatementKind,
+    // Whether it leaves the database as it found it
+    pub read_only: bool,
+    pub returns_rows: bool,
+    // Whether no other connection can use the database while it runs. E.g., VACUUM.
+    pub needs_exclusive: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum StatementKind {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    // CREATE, DROP and ALTER
+    Ddl,
+    Pragma,
+    // BEGIN, COMMIT, ROLLBACK, SAVEPOINT and RELEASE
+    Transaction,
+    // ATTACH and DETACH
+    Attach,
+    // EXPLAIN and EXPLAIN QUERY PLAN
+    Explain,
+    // ANALYZE, REINDEX and VACUUM
+    Other,
+}
+
+// The relations a statement reads and writes, as `db.name` spelled as declared.
+// Lets a live query know when it needs to run again.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Dependencies {
+    pub reads: Vec<RelationName>,
+    pub writes: Vec<RelationName>,
+    // `(relation, column)` pairs. Only filled in with the `column_dependencies` option.
+    pub read_columns: Vec<(RelationName, ColName)>,
+    pub write_columns: Vec<(RelationName, ColName)>,
+    // Views and triggers whose recorded SQL couldn't be followed. What they read and write is missing above.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// The table column a result column is read straight from, as `sqlite3_column_database_name`,
+// `sqlite3_column_table_name` and `sqlite3_column_origin_name` report it. Subqueries and CTEs are seen through.
+// Tells tools which cells can be written back and to where.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ColumnOrigin {
+    pub db: String,
+    pub table: String,
+    pub column: ColName,
+}
+
+// How many rows a statement returns. Lets a runtime pick between fetching one row and fetching them all.
+// `Zero` is for statements that don't return rows at all, like an INSERT without RETURNING.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Cardinality {
+    Zero,
+    One,
+    ZeroOrOne,
+    Many,
+}
+
+// A bound parameter of a statement. `index` is what gets passed to sqlite3_bind_*.
+// `name` is the parameter as written (`:id`, `?3`) or None for a bare `?`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Param {
+    pub index: usize,
+    pub name: Option<String>,
+    pub col_type: ColType,
+}
+
+// Tells a runtime to decode the column at `position` into `type_name`.
+// E.g., JSON.parse a `Json` column or turn the 0/1 of a `Boolean` column into a bool.
+// Mirrors Python sqlite3's converters: https://docs.python.org/3/library/sqlite3.html#sqlite3-converters
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Converter {
+    pub position: usize,
+    pub type_name: String,
+    // The affinity of the column's declared type, which decides how SQLite stores the values.
+    // E.g., a `JSON` column has NUMERIC affinity, so text that looks like a number comes back as a number.
+    pub affinity: Affinity,
+}
+
+// https://www.sqlite.org/datatype3.html#type_affinity
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Affinity {
+    Integer,
+    Text,
+    Blob,
+    Real,
+    Numeric,
+}
 
 pub fn builtin_type(c: BuiltinType) -> ColType {
-    return vec![(TypeKind::Builtin, Some(c), None)];
+    vec![(TypeKind::Builtin, Some(c), None)]
+}
+
+// The type of a column we couldn't type, along with why.
+pub fn unresolved_type(reason: String) -> ColType {
+    vec![(TypeKind::Unresolved, None, Some(reason))]
+}
+
+pub fn literal_to_type(lit: &Literal) -> ColType {
+    match lit {
+        Literal::Numeric(l) => vec![(
+            TypeKind::Literal,
+            Some(BuiltinType::Number),
+            Some(l.to_string()),
+        )],
+        Literal::String(l) => vec![(
+            TypeKind::Literal,
+            Some(BuiltinType::String),
+            Some(l.to_string()),
+        )],
+        Literal::Blob(l) => vec![(
+            TypeKind::Literal,
+            Some(BuiltinType::Blob),
+            Some(l.to_string()),
+        )],
+        // TODO: what does it mean for a keyword literal to exist in a position the should result in a value?
+        Literal::Keyword(l) => vec![(TypeKind::Literal, None, Some(l.to_string()))],
+        Literal::Null => builtin_type(BuiltinType::Null),
+        Literal::CurrentDate => vec![(TypeKind::Literal, Some(BuiltinType::CurrentDate), None)],
+        Literal::CurrentTime => vec![(TypeKind::Literal, Some(BuiltinType::CurrentTime), None)],
+        Literal::CurrentTimestamp => {
+            vec![(TypeKind::Literal, Some(BuiltinType::CurrentTimestamp), None)]
+        }
+    }
+}
+
+// https://www.sqlite.org/datatype3.html#determination_of_column_affinity
+// Rules are applied in order. A missing type name gets BLOB affinity but the caller should handle that case
+// since it is `Unspecified` to us.
+pub fn affinity(type_name: &str) -> Affinity {
+    let uppered = type_name.to_uppercase();
+    if uppered.contains("INT") {
+        Affinity::Integer
+    } else if uppered.contains("CHAR") || uppered.contains("CLOB") || uppered.contains("TEXT") {
+        Affinity::Text
+    } else if uppered.contains("BLOB") {
+        Affinity::Blob
+    } else if uppered.contains("REAL") || uppered.contains("FLOA") || uppered.contains("DOUB") {
+        Affinity::Real
+    } else {
+        Affinity::Numeric
+    }
+}
+
+pub fn affinity_type(affinity: Affinity) -> ColType {
+    builtin_type(match affinity {
+        Affinity::Integer => BuiltinType::Int,
+        Affinity::Text => BuiltinType::String,
+        Affinity::Blob => BuiltinType::Blob,
+        Affinity::Real => BuiltinType::Float,
+        Affinity::Numeric => BuiltinType::Number,
+    })
+}
+
+// The only type names a STRICT table accepts.
+// https://www.sqlite.org/stricttables.html
+pub fn is_strict_type_name(type_name: &str) -> bool {
+    ["int", "integer", "real", "text", "blob", "any"]
+        .iter()
+        .any(|t| t.eq_ignore_ascii_case(type_name))
+}
+
+// The type for a type as written in DDL or a CAST. `strict` is whether it's a column of a STRICT table.
+// User mappings win, then affinity if the user asked for storage types, then our own interpretation of the name.
+pub fn type_from_declared_type(declared: &Type, strict: bool, options: &Options) -> ColType {
+    if let Some(t) = mapped_type(declared, &options.type_mappings) {
+        builtin_type(t)
+    } else if strict && declared.name.eq_ignore_ascii_case("any") {
+        // a STRICT table stores what an ANY column is given as is, without applying an affinity
+        builtin_type(BuiltinType::Any)
+    } else if options.affinity_types {
+        affinity_type(affinity(&declared.name))
+    } else {
+        type_from_type_name(declared.name.to_string())
+    }
+}
+
+// A pattern without a size (`UUID`) matches the name regardless of size (`UUID`, `UUID(36)`).
+// A pattern with a size (`VARCHAR(*)`) is matched against the name and size.
+fn mapped_type(declared: &Type, mappings: &[(String, BuiltinType)]) -> Option<BuiltinType> {
+    let name = normalize_type_name(&declared.name);
+    let sized_name = match &declared.size {
+        None => name.clone(),
+        Some(TypeSize::MaxSize(size)) => normalize_type_name(&format!("{}({})", name, size)),
+        Some(TypeSize::TypeSize(size1, size2)) => {
+            normalize_type_name(&format!("{}({},{})", name, size1, size2))
+        }
+    };
+    mappings.iter().find_map(|(pattern, t)| {
+        let pattern = normalize_type_name(pattern);
+        let subject = if pattern.contains('(') {
+            &sized_name
+        } else {
+            &name
+        };
+        if glob_matches(pattern.as_bytes(), subject.as_bytes()) {
+            Some(t.clone())
+        } else {
+            None
+        }
+    })
+}
+
+// `UNSIGNED big  int` and `unsigned BIG INT` are the same type to SQLite
+fn normalize_type_name(name: &str) -> String {
+    name.split_whitespace().collect::<String>().to_uppercase()
+}
+
+// `*` is the only special character.
+// On a mismatch the last `*` takes one more byte, which keeps this linear in the number of `*`.
+fn glob_matches(pattern: &[u8], subject: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // where the last `*` was and where its match ends
+    let mut star = None;
+    while s < subject.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, s));
+                p += 1;
+            }
+            Some(c) if *c == subject[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match star {
+                Some((star_p, star_s)) => {
+                    star = Some((star_p, star_s + 1));
+                    p = star_p + 1;
+                    s = star_s + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+// Columns whose values SQLite can't return as their declared type get a converter.
+// That is custom types and the builtins SQLite has no storage class for.
+// The affinity comes from the declared type of the table column a result column is read from.
+// Computed columns, and tables from schemas that don't record declared types, go by the name we'd read as the type.
+pub fn converters(
+    relation: &Relation,
+    origins: &[Option<ColumnOrigin>],
+    schema: &Schema,
+) -> Vec<Converter> {
+    relation
+        .1
+        .iter()
+        .enumerate()
+        .filter_map(|(position, (_, col_type))| {
+            col_type
+                .iter()
+                .find_map(|t| match t {
+                    (TypeKind::Custom, _, Some(type_name)) => Some(type_name.to_string()),
+                    (
+                        TypeKind::Builtin,
+                        Some(
+                            b @ (BuiltinType::Boolean
+                            | BuiltinType::Date
+                            | BuiltinType::Time
+                            | BuiltinType::Json),
+                        ),
+                        _,
+                    ) => Some(format!("{:?}", b)),
+                    _ => None,
+                })
+                .map(|type_name| {
+                    let declared = origins
+                        .get(position)
+                        .and_then(|origin| origin.as_ref())
+                        .and_then(|origin| declared_type(origin, schema));
+                    Converter {
+                        position,
+                        affinity: affinity(declared.unwrap_or(&type_name)),
+                        type_name,
+                    }
+                })
+        })
+        .collect()
+}
+
+fn declared_type<'a>(origin: &'a ColumnOrigin, schema: &'a Schema) -> Option<&'a str> {
+    let (_, (cols, info)) = schema.get(Some(&origin.db), &origin.table)?;
+    let i = cols
+        .iter()
+        .position(|(name, _)| util::ident_eq(name, &origin.column))?;
+    info.declared_types.get(i)?.as_deref()
 }
 
 // This type name could be:
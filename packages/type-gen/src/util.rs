@@ -1,7 +1,125 @@
-pub fn unquote_ident(s: &str) -> &str {
-    if s.starts_with('"') || s.starts_with('[') || s.starts_with('`') {
-        &s[1..s.len() - 1]
+use std::borrow::Cow;
+
+// Identifiers can be quoted with "", [], `` and, in places SQLite tolerates it, ''.
+// Strip the quotes and collapse any doubled closing quote. `"say ""hi"""` -> `say "hi"`
+// https://www.sqlite.org/lang_keywords.html
+pub fn unquote_ident(s: &str) -> Cow<'_, str> {
+    let close = match s.chars().next() {
+        Some('"') => '"',
+        Some('`') => '`',
+        Some('[') => ']',
+        Some('\'') => '\'',
+        _ => return Cow::Borrowed(s),
+    };
+    if s.len() < 2 || !s.ends_with(close) {
+        return Cow::Borrowed(s);
+    }
+    let inner = &s[1..s.len() - 1];
+    let doubled = format!("{}{}", close, close);
+    if inner.contains(&doubled) {
+        Cow::Owned(inner.replace(&doubled, &close.to_string()))
+    } else {
+        Cow::Borrowed(inner)
+    }
+}
+
+// Identifiers are compared case-insensitively, but only for ASCII. SQLite doesn't fold anything else.
+// Both sides should already be unquoted. The declared spelling is what we hand back out.
+pub fn ident_eq(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+// For when identifiers need to be hashed. Two identifiers have the same key iff `ident_eq`.
+pub fn ident_key(s: &str) -> String {
+    s.to_ascii_lowercase()
+}
+
+// Edit distance between two identifiers, ignoring ASCII case like `ident_eq`.
+// Swapping two adjacent characters counts as one edit since that's a common typo.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.to_ascii_lowercase().chars().collect::<Vec<_>>();
+    let b = b.to_ascii_lowercase().chars().collect::<Vec<_>>();
+    // d[i][j] is the distance between the first i characters of a and the first j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j - 1] + cost)
+                .min(d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+// The candidates `name` is most likely a typo of, closest first.
+// Allows about one edit for every three characters of `name`.
+pub fn similar_idents<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut ret = candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .collect::<Vec<_>>();
+    ret.sort();
+    ret.dedup_by(|a, b| ident_eq(a.1, b.1));
+    ret.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+// Appended to "no such ..." messages. Empty when nothing is close.
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    let similar = similar_idents(name, candidates);
+    if similar.is_empty() {
+        String::new()
     } else {
-        s
+        format!(". Did you mean {}?", similar.join(" or "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unquoting() {
+        assert_eq!(unquote_ident("foo"), "foo");
+        assert_eq!(unquote_ident("\"foo bar\""), "foo bar");
+        assert_eq!(unquote_ident("\"say \"\"hi\"\"\""), "say \"hi\"");
+        assert_eq!(unquote_ident("[a]]b]"), "a]b");
+        assert_eq!(unquote_ident("`a``b`"), "a`b");
+        assert_eq!(unquote_ident("'it''s'"), "it's");
+        assert_eq!(unquote_ident("\""), "\"");
+    }
+
+    #[test]
+    fn comparing() {
+        assert!(ident_eq("Foo", "fOO"));
+        assert!(!ident_eq("Ä", "ä"));
+        assert_eq!(ident_key("FooBar"), ident_key("fooBAR"));
+    }
+
+    #[test]
+    fn suggesting() {
+        assert_eq!(edit_distance("kitten", "SITTING"), 3);
+        assert_eq!(edit_distance("usres", "users"), 1);
+        assert_eq!(
+            similar_idents("usres", ["posts", "user_roles", "users", "uses"]),
+            vec!["users", "uses"]
+        );
+        assert_eq!(
+            did_you_mean("titel", ["title", "id"]),
+            ". Did you mean title?"
+        );
+        assert_eq!(did_you_mean("xyz", ["title", "id"]), "");
     }
 }
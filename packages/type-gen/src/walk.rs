@@ -0,0 +1,99 @@
+use sqlite3_parser::ast::{Expr, FunctionTail, Over};
+
+// Calls `f` with `expr` and then every expression nested within it.
+// Subqueries are handed to `f` but not entered since they bring their own scope.
+pub fn walk_expr<'a>(expr: &'a Expr, f: &mut dyn FnMut(&'a Expr)) {
+    f(expr);
+    match expr {
+        Expr::Between {
+            lhs, start, end, ..
+        } => {
+            walk_expr(lhs, f);
+            walk_expr(start, f);
+            walk_expr(end, f);
+        }
+        Expr::Binary(lhs, _, rhs) => {
+            walk_expr(lhs, f);
+            walk_expr(rhs, f);
+        }
+        Expr::Case {
+            base,
+            when_then_pairs,
+            else_expr,
+        } => {
+            if let Some(base) = base {
+                walk_expr(base, f);
+            }
+            for (when, then) in when_then_pairs {
+                walk_expr(when, f);
+                walk_expr(then, f);
+            }
+            if let Some(else_expr) = else_expr {
+                walk_expr(else_expr, f);
+            }
+        }
+        Expr::Cast { expr, .. } | Expr::Collate(expr, _) => walk_expr(expr, f),
+        Expr::FunctionCall {
+            args, filter_over, ..
+        } => {
+            for arg in args.iter().flatten() {
+                walk_expr(arg, f);
+            }
+            walk_function_tail(filter_over, f);
+        }
+        Expr::FunctionCallStar { filter_over, .. } => walk_function_tail(filter_over, f),
+        Expr::InList { lhs, rhs, .. } => {
+            walk_expr(lhs, f);
+            for e in rhs.iter().flatten() {
+                walk_expr(e, f);
+            }
+        }
+        Expr::InSelect { lhs, .. } => walk_expr(lhs, f),
+        Expr::InTable { lhs, args, .. } => {
+            walk_expr(lhs, f);
+            for arg in args.iter().flatten() {
+                walk_expr(arg, f);
+            }
+        }
+        Expr::IsNull(e) | Expr::NotNull(e) | Expr::Unary(_, e) => walk_expr(e, f),
+        Expr::Like {
+            lhs, rhs, escape, ..
+        } => {
+            walk_expr(lhs, f);
+            walk_expr(rhs, f);
+            if let Some(escape) = escape {
+                walk_expr(escape, f);
+            }
+        }
+        Expr::Parenthesized(exprs) => {
+            for e in exprs {
+                walk_expr(e, f);
+            }
+        }
+        Expr::DoublyQualified(..)
+        | Expr::Exists(_)
+        | Expr::Id(_)
+        | Expr::Literal(_)
+        | Expr::Name(_)
+        | Expr::Qualified(..)
+        | Expr::Raise(..)
+        | Expr::Subquery(_)
+        | Expr::Variable(_) => {}
+    }
+}
+
+fn walk_function_tail<'a>(tail: &'a Option<FunctionTail>, f: &mut dyn FnMut(&'a Expr)) {
+    if let Some(tail) = tail {
+        if let Some(filter) = &tail.filter_clause {
+            walk_expr(filter, f);
+        }
+        if let Some(Over::Window(window)) = tail.over_clause.as_deref() {
+            for e in window.partition_by.iter().flatten() {
+                walk_expr(e, f);
+            }
+            for term in window.order_by.iter().flatten() {
+                walk_expr(&term.expr, f);
+            }
+        }
+    }
+}